@@ -3,6 +3,7 @@ use std::error::Error;
 fn main() -> Result<(), Box<dyn Error>> {
     // Compile gRPC .proto files.
     tonic_prost_build::compile_protos("proto/cogito.proto")?;
+    tonic_prost_build::compile_protos("proto/cogito_api.proto")?;
 
     Ok(())
 }
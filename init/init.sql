@@ -8,9 +8,12 @@ CREATE TABLE users
     user_name       TEXT NOT NULL,
     user_pass       TEXT NOT NULL,
     user_last_login TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
-    login_id        UUID DEFAULT NULL UNIQUE,
     verified        BOOLEAN NOT NULL DEFAULT FALSE,
-    admin           BOOLEAN NOT NULL DEFAULT FALSE
+    admin           BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Identifies the user to the agent without revealing who they are.
+    pseudonym       UUID NOT NULL UNIQUE DEFAULT gen_random_uuid(),
+    -- Always ask the agent instead of reusing answers to identical questions.
+    answer_cache_opt_out BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 CREATE TABLE conversations (
@@ -20,14 +23,181 @@ CREATE TABLE conversations (
     conversation       JSON NOT NULL,
     conversation_title TEXT NOT NULL DEFAULT 'new conversation',
 
-    created_at         TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    created_at         TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    -- organization, only changed by the user.
+    archived           BOOLEAN NOT NULL DEFAULT FALSE,
+    folder             TEXT DEFAULT NULL,
+    tags               TEXT[] NOT NULL DEFAULT '{}',
+
+    -- identifies the conversation to the agent.
+    pseudonym          UUID NOT NULL UNIQUE DEFAULT gen_random_uuid(),
+    -- agent backend the conversation is pinned to, balanced between all of them if null.
+    agent_backend      TEXT DEFAULT NULL,
+    -- summary of the earliest turns, sent to the agent instead of them once they don't fit.
+    context_summary    JSON DEFAULT NULL
 );
 
 -- allow indexing by user_id for fetching all user convos.
 CREATE INDEX idx_conversations_user_id ON conversations(user_id);
 
+CREATE TABLE documents (
+    document_id     SERIAL PRIMARY KEY NOT NULL,
+    user_id         INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    -- null until the document is given to a conversation.
+    conversation_id INTEGER DEFAULT NULL REFERENCES conversations(conversation_id) ON DELETE CASCADE,
+
+    file_name       TEXT NOT NULL,
+    media_type      TEXT NOT NULL,
+    -- only the extracted text is stored, not the original file.
+    content         TEXT NOT NULL,
+
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_documents_conversation_id ON documents(conversation_id);
+
+-- append-only, so revisions outlive their conversation. deleting a conversation adds a 'deleted'
+-- revision instead. deleting the user still removes everything.
+CREATE TABLE conversation_revisions (
+    revision_id        SERIAL PRIMARY KEY NOT NULL,
+    conversation_id    INTEGER NOT NULL,
+    user_id            INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+
+    -- revisions are numbered per conversation starting at 1.
+    revision_number    INTEGER NOT NULL,
+    revision_kind      TEXT NOT NULL,
+
+    -- full snapshot of the conversation after the change.
+    conversation       JSON NOT NULL,
+    conversation_title TEXT NOT NULL,
+    archived           BOOLEAN NOT NULL,
+    folder             TEXT DEFAULT NULL,
+    tags               TEXT[] NOT NULL,
+
+    created_at         TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    UNIQUE (conversation_id, revision_number)
+);
+
+CREATE TABLE annotations (
+    annotation_id   SERIAL PRIMARY KEY NOT NULL,
+    conversation_id INTEGER NOT NULL REFERENCES conversations(conversation_id) ON DELETE CASCADE,
+    user_id         INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+
+    -- JSON pointer to the annotated string inside the conversation and the character range of the
+    -- highlight. The quote and surrounding context are kept to re-anchor after regeneration.
+    anchor_path     TEXT NOT NULL,
+    anchor_start    INTEGER NOT NULL,
+    anchor_end      INTEGER NOT NULL,
+    anchor_quote    TEXT NOT NULL,
+    anchor_prefix   TEXT NOT NULL DEFAULT '',
+    anchor_suffix   TEXT NOT NULL DEFAULT '',
+
+    note            TEXT DEFAULT NULL,
+    color           TEXT NOT NULL DEFAULT 'yellow',
+
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- annotations are always fetched per conversation.
+CREATE INDEX idx_annotations_conversation_id ON annotations(conversation_id);
+
+CREATE TABLE prompt_templates (
+    template_id SERIAL PRIMARY KEY NOT NULL,
+    user_id     INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+
+    title       TEXT NOT NULL,
+    -- prompt text with `{placeholder}` variables.
+    body        TEXT NOT NULL,
+    -- shared templates are visible to every user.
+    shared      BOOLEAN NOT NULL DEFAULT FALSE,
+
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- answers to identical questions, keyed by a hash of the normalized question, options and agent
+-- version.
+CREATE TABLE answer_cache
+(
+    cache_key        TEXT PRIMARY KEY NOT NULL,
+    agent_version    TEXT NOT NULL,
+    -- model that answered, empty if the agent didn't say.
+    model            TEXT NOT NULL DEFAULT '',
+
+    content          TEXT NOT NULL,
+    protocol_version INTEGER NOT NULL,
+
+    hits             INTEGER NOT NULL DEFAULT 0,
+    created_at       TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at       TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_answer_cache_expires_at ON answer_cache(expires_at);
+
+CREATE TABLE comparisons (
+    comparison_id  SERIAL PRIMARY KEY NOT NULL,
+    user_id        INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+
+    -- the same question answered by two variants, shown to the user as a and b.
+    question       TEXT NOT NULL,
+    conversation_a INTEGER NOT NULL REFERENCES conversations(conversation_id) ON DELETE CASCADE,
+    conversation_b INTEGER NOT NULL REFERENCES conversations(conversation_id) ON DELETE CASCADE,
+    -- backend and options of each side, only revealed after voting to keep the vote blind.
+    variant_a      JSON NOT NULL,
+    variant_b      JSON NOT NULL,
+
+    vote           TEXT DEFAULT NULL CHECK (vote IN ('a', 'b', 'tie')),
+    voted_at       TIMESTAMPTZ DEFAULT NULL,
+
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_comparisons_user_id ON comparisons(user_id);
+
+-- one row per logged in device, so logging in somewhere doesn't log out everywhere else.
+CREATE TABLE sessions (
+    session_id  SERIAL PRIMARY KEY NOT NULL,
+    user_id     INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+
+    -- the secret sent as cookie or bearer token, never shown again after login.
+    login_id    UUID NOT NULL UNIQUE,
+
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- sessions expire after a while without requests.
+    last_seen   TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    user_agent  TEXT DEFAULT NULL,
+    ip          TEXT DEFAULT NULL
+);
+
+CREATE INDEX idx_sessions_user_id ON sessions(user_id);
+
 ALTER TABLE users
     OWNER TO postgres;
 
 ALTER TABLE conversations
     OWNER TO postgres;
+
+ALTER TABLE documents
+    OWNER TO postgres;
+
+ALTER TABLE conversation_revisions
+    OWNER TO postgres;
+
+ALTER TABLE annotations
+    OWNER TO postgres;
+
+ALTER TABLE prompt_templates
+    OWNER TO postgres;
+
+ALTER TABLE answer_cache
+    OWNER TO postgres;
+
+ALTER TABLE comparisons
+    OWNER TO postgres;
+
+ALTER TABLE sessions
+    OWNER TO postgres;
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::agent::CogitoAgent;
+use crate::api_messages::{AGENT_FAILED_TO_COMMUNICATE, BAD_SESSION, GenericResponse};
+use crate::login::validate_session;
+use crate::proto::{ActionDecision, ApprovalRequest};
+use actix_web::web::{Data, Form, Json, Path};
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, get, post};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tonic::Code;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// The agent may pause while answering and ask the user to approve an action first, such as
+// fetching an external source. It announces this on the progress stream and waits until the API
+// tells it the decision. Pending actions only matter while the agent waits, so they live in memory.
+//
+// Action ids are only unique to the agent backend that made them, so users are given an id of our
+// own for every action instead.
+
+/// How long a user gets to decide when the agent doesn't say.
+const DEFAULT_DECISION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The message returned when an action isn't pending for the user.
+static ACTION_NOT_FOUND: &str = "Action not found.";
+
+/// An action waiting for the user's approval.
+struct PendingAction {
+    user_id: i32,
+    /// Pseudonym of the conversation being created.
+    conversation: Uuid,
+    /// The agent backend waiting for the decision.
+    backend: String,
+    /// The id the agent knows the action by.
+    agent_action_id: String,
+    info: PendingActionInfo,
+}
+
+/// An action the agent wants the user to approve before going on.
+#[derive(Serialize, ToSchema, Clone)]
+pub struct PendingActionInfo {
+    #[schema(value_type = String, format = "uuid")]
+    action_id: Uuid,
+    /// What sort of action this is, e.g. "fetch_source".
+    kind: String,
+    description: String,
+    /// When the action is denied if the user hasn't decided.
+    #[schema(value_type = String, format = "date-time")]
+    expires_at: DateTime<Utc>,
+}
+
+/// Post request data to decide on a pending action.
+#[derive(Deserialize, ToSchema)]
+pub struct ActionDecisionRequest {
+    approved: bool,
+    /// Why the action was denied, passed on to the agent.
+    reason: Option<String>,
+}
+
+/// Actions waiting for approval, for every user.
+#[derive(Default)]
+pub struct ActionRegistry {
+    pending: Mutex<HashMap<Uuid, PendingAction>>,
+}
+
+impl ActionRegistry {
+    /// Record an action the agent asked approval for.
+    ///
+    /// If the user doesn't decide in time the action is denied on their behalf. The agent stops
+    /// waiting once its call times out, so that is as long as the user gets.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        request: ApprovalRequest,
+        backend: String,
+        user_id: i32,
+        conversation: Uuid,
+        agent: CogitoAgent,
+    ) -> PendingActionInfo {
+        let timeout = match request.timeout_secs {
+            0 => DEFAULT_DECISION_TIMEOUT,
+            secs => Duration::from_secs(secs.into()),
+        }
+        .min(agent.timeout());
+
+        let info = PendingActionInfo {
+            action_id: Uuid::new_v4(),
+            kind: request.kind,
+            description: request.description,
+            expires_at: Utc::now()
+                + chrono::Duration::from_std(timeout).unwrap_or(chrono::Duration::zero()),
+        };
+
+        self.pending.lock().unwrap().insert(
+            info.action_id,
+            PendingAction {
+                user_id,
+                conversation,
+                backend,
+                agent_action_id: request.action_id,
+                info: info.clone(),
+            },
+        );
+
+        let registry = Arc::clone(self);
+        let action_id = info.action_id;
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+
+            let Some(action) = registry.pending.lock().unwrap().remove(&action_id) else {
+                return;
+            };
+
+            info!("Action {} timed out waiting for approval.", action_id);
+            let decision = ActionDecision {
+                action_id: action.agent_action_id,
+                approved: false,
+                reason: "The user didn't decide in time.".to_string(),
+            };
+            // The agent most likely gave up waiting by now as well.
+            if let Err(status) = agent.resolve_action(&action.backend, decision).await {
+                warn!("Failed to deny timed out action: {}", status);
+            }
+        });
+
+        info
+    }
+
+    /// Take an action out of the registry, if it is pending for the user.
+    fn take(&self, action_id: Uuid, user_id: i32) -> Option<PendingAction> {
+        let mut pending = self.pending.lock().unwrap();
+
+        match pending.get(&action_id) {
+            Some(action) if action.user_id == user_id => pending.remove(&action_id),
+            _ => None,
+        }
+    }
+
+    /// Drop whatever is still pending for a conversation once the agent is done with it.
+    pub(crate) fn forget_conversation(&self, conversation: Uuid) {
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|_, action| action.conversation != conversation);
+    }
+
+    fn pending_for(&self, user_id: i32) -> Vec<PendingActionInfo> {
+        self.pending
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|action| action.user_id == user_id)
+            .map(|action| action.info.clone())
+            .collect()
+    }
+}
+
+/// Get every action waiting for the user's approval.
+///
+/// These are also announced on the progress stream of the conversation being created.
+#[utoipa::path(
+    get,
+    path = "/actions",
+    responses(
+        (status = 200, description = "Pending actions retrieved successfully.", body = Vec<PendingActionInfo>),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+    )
+)]
+#[get("/actions")]
+pub async fn get_pending_actions(
+    req: HttpRequest,
+    db: Data<PgPool>,
+    registry: Data<ActionRegistry>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    HttpResponse::Ok().json(registry.pending_for(user.user_id))
+}
+
+/// Approve or deny an action the agent is waiting on.
+#[utoipa::path(
+    post,
+    path = "/actions/{action_id}",
+    params(
+        ("action_id" = String, Path, format = "uuid", description = "The ID of the pending action.")
+    ),
+    request_body = ActionDecisionRequest,
+    responses(
+        (status = 200, description = "Decision passed on to the agent.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = ACTION_NOT_FOUND, body = GenericResponse),
+        (status = 410, description = "The agent is no longer waiting for this action.", body = GenericResponse),
+        (status = 500, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+        (status = 503, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+    )
+)]
+#[post("/actions/{action_id}")]
+pub async fn resolve_action(
+    action_id: Path<Uuid>,
+    req: HttpRequest,
+    info: Either<Json<ActionDecisionRequest>, Form<ActionDecisionRequest>>,
+    db: Data<PgPool>,
+    cogito_agent: Data<CogitoAgent>,
+    registry: Data<ActionRegistry>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    // Expired actions and those of other users look the same as unknown ones.
+    let Some(action) = registry.take(*action_id, user.user_id) else {
+        return HttpResponse::NotFound().json(GenericResponse {
+            message: ACTION_NOT_FOUND,
+        });
+    };
+
+    let ActionDecisionRequest { approved, reason } = info.into_inner();
+    let decision = ActionDecision {
+        action_id: action.agent_action_id,
+        approved,
+        reason: reason.unwrap_or_default(),
+    };
+
+    match cogito_agent.resolve_action(&action.backend, decision).await {
+        Ok(()) => HttpResponse::Ok().json(GenericResponse {
+            message: "Decision passed on to the agent.",
+        }),
+        Err(status) => {
+            error!(
+                "Failed to pass a decision from user {} to the Cogito agent: {}",
+                user.user_name, status
+            );
+
+            match status.code() {
+                Code::NotFound | Code::FailedPrecondition => {
+                    HttpResponse::Gone().json(GenericResponse {
+                        message: "The agent is no longer waiting for this action.",
+                    })
+                }
+                Code::Unavailable => HttpResponse::ServiceUnavailable().json(GenericResponse {
+                    message: AGENT_FAILED_TO_COMMUNICATE,
+                }),
+                _ => HttpResponse::InternalServerError().json(GenericResponse {
+                    message: AGENT_FAILED_TO_COMMUNICATE,
+                }),
+            }
+        }
+    }
+}
@@ -1,24 +1,757 @@
+mod backend;
+mod breaker;
+mod health;
+mod tls;
+
 use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::agent::backend::{AgentBackend, OutstandingRequest};
+use crate::agent::breaker::CircuitBreaker;
 use crate::proto::cogito_client::CogitoClient;
+use crate::proto::{
+    ActionDecision, Answer, ApprovalRequest, Capabilities, CapabilitiesRequest, Progress, Question,
+    SummaryRequest, ask_event,
+};
+use log::warn;
+use rand::Rng;
+use serde::Serialize;
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::transport::Channel;
+use tonic::{Code, Status};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub use crate::agent::breaker::BreakerStatus;
+pub use crate::agent::health::AgentHealth;
+pub use crate::agent::tls::AgentTls;
+
+/// How requests are spread over the configured agent backends.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Balancing {
+    /// Take turns between backends.
+    RoundRobin,
+    /// Prefer the backend with the fewest requests in flight.
+    LeastOutstanding,
+}
+
+impl FromStr for Balancing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(Balancing::RoundRobin),
+            "least_outstanding" => Ok(Balancing::LeastOutstanding),
+            other => Err(format!("Unknown agent balancing strategy \"{}\".", other)),
+        }
+    }
+}
+
+/// Settings for connecting to and calling the Cogito agent.
+pub struct AgentConfig {
+    /// Addresses of every agent replica.
+    pub urls: Vec<String>,
+    pub balancing: Balancing,
+    /// Deadline for a single call, sent to the agent as `grpc-timeout`.
+    pub timeout: Duration,
+    /// How many more times a call is attempted after a retryable failure.
+    pub max_retries: u32,
+    /// Base delay between retries, doubled on every attempt and jittered.
+    pub retry_backoff: Duration,
+    /// Consecutive failures after which a backend's circuit breaker opens.
+    pub breaker_threshold: u32,
+    /// How long an open circuit breaker fails fast before letting a trial request through.
+    pub breaker_cooldown: Duration,
+    /// Time between health checks of every backend.
+    pub health_interval: Duration,
+    /// How long the agent's capabilities are cached.
+    pub capabilities_ttl: Duration,
+    /// Connect over TLS instead of plaintext.
+    pub tls: Option<AgentTls>,
+    /// Shared secret proving to the agent that calls come from the API.
+    pub token: Option<String>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            urls: Vec::new(),
+            balancing: Balancing::RoundRobin,
+            // Deep research can take several minutes.
+            timeout: Duration::from_secs(300),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(250),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+            health_interval: Duration::from_secs(10),
+            capabilities_ttl: Duration::from_secs(300),
+            tls: None,
+            token: None,
+        }
+    }
+}
+
+/// Read an optional environment variable, falling back to `default` if it does not exist.
+fn env_or<T>(name: &str, default: T) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| format!("Invalid `{}` environment variable: {}", name, e).into()),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Read TLS settings for the Cogito agent.
+///
+/// TLS is used if `COGITO_AGENT_TLS` is "true" or any of the certificate settings exist.
+fn agent_tls_from_env() -> Result<Option<AgentTls>, Box<dyn Error>> {
+    let var = |name: &str| std::env::var(name).ok();
+
+    let tls = AgentTls {
+        ca_certificate: var("COGITO_AGENT_TLS_CA").map(PathBuf::from),
+        client_certificate: var("COGITO_AGENT_TLS_CERT").map(PathBuf::from),
+        client_key: var("COGITO_AGENT_TLS_KEY").map(PathBuf::from),
+        domain: var("COGITO_AGENT_TLS_DOMAIN"),
+    };
+
+    let configured = tls.ca_certificate.is_some()
+        || tls.client_certificate.is_some()
+        || tls.client_key.is_some()
+        || tls.domain.is_some();
+
+    if env_or("COGITO_AGENT_TLS", false)? || configured {
+        Ok(Some(tls))
+    } else {
+        Ok(None)
+    }
+}
+
+impl AgentConfig {
+    /// Read the configuration from `COGITO_AGENT_*` environment variables.
+    ///
+    /// `COGITO_AGENT_URL` is required and may list several comma separated agent replicas,
+    /// everything else falls back to the defaults.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let agent_urls = std::env::var("COGITO_AGENT_URL")
+            .map_err(|_| "Expected `COGITO_AGENT_URL` environment variable.")?;
+
+        let defaults = AgentConfig::default();
+
+        let balancing = match std::env::var("COGITO_AGENT_BALANCING") {
+            Ok(balancing) => balancing.parse::<Balancing>()?,
+            Err(_) => defaults.balancing,
+        };
+
+        Ok(AgentConfig {
+            urls: agent_urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(String::from)
+                .collect(),
+            balancing,
+            timeout: Duration::from_secs(env_or(
+                "COGITO_AGENT_TIMEOUT_SECS",
+                defaults.timeout.as_secs(),
+            )?),
+            max_retries: env_or("COGITO_AGENT_MAX_RETRIES", defaults.max_retries)?,
+            retry_backoff: defaults.retry_backoff,
+            breaker_threshold: env_or(
+                "COGITO_AGENT_BREAKER_THRESHOLD",
+                defaults.breaker_threshold,
+            )?,
+            breaker_cooldown: Duration::from_secs(env_or(
+                "COGITO_AGENT_BREAKER_COOLDOWN_SECS",
+                defaults.breaker_cooldown.as_secs(),
+            )?),
+            health_interval: Duration::from_secs(env_or(
+                "COGITO_AGENT_HEALTH_INTERVAL_SECS",
+                defaults.health_interval.as_secs(),
+            )?),
+            capabilities_ttl: Duration::from_secs(env_or(
+                "COGITO_AGENT_CAPABILITIES_TTL_SECS",
+                defaults.capabilities_ttl.as_secs(),
+            )?),
+            tls: agent_tls_from_env()?,
+            token: std::env::var("COGITO_AGENT_TOKEN").ok(),
+        })
+    }
+}
+
+/// What a call to the agent is made on behalf of.
+///
+/// This is sent to the agent as gRPC metadata, so it can enforce access and its logs can be
+/// correlated with ours. Users and conversations are only identified by pseudonyms.
+pub struct CallContext {
+    /// Correlation id of the HTTP request that led to this call.
+    pub request_id: String,
+    /// Pseudonym of the user asking.
+    pub user: Uuid,
+    /// Pseudonym of the conversation the call is for.
+    pub conversation: Uuid,
+    /// URL of the backend the conversation is pinned to. Calls only go there instead of being
+    /// balanced. This is not sent to the agent.
+    pub backend: Option<String>,
+}
+
+impl CallContext {
+    fn write_metadata(&self, metadata: &mut MetadataMap) {
+        // Pseudonyms are always valid metadata, request ids are validated when they are assigned.
+        if let Ok(request_id) = self.request_id.parse() {
+            metadata.insert("x-request-id", request_id);
+        }
+        if let Ok(user) = self.user.to_string().parse() {
+            metadata.insert("x-cogito-user", user);
+        }
+        if let Ok(conversation) = self.conversation.to_string().parse() {
+            metadata.insert("x-cogito-conversation", conversation);
+        }
+    }
+}
+
+/// Something the agent reported while researching.
+pub enum AgentEvent {
+    Progress(Progress),
+    /// The agent waits for the user to approve an action, see `CogitoAgent::resolve_action`.
+    ApprovalRequested {
+        request: ApprovalRequest,
+        /// The backend waiting for the decision.
+        backend: String,
+    },
+}
+
+/// State of a single agent backend as reported for monitoring.
+#[derive(Serialize, ToSchema)]
+pub struct BackendStatus {
+    url: String,
+    /// Whether the backend is reachable and hasn't reported itself as not serving.
+    healthy: bool,
+    /// Result of the latest `grpc.health.v1` check.
+    health: AgentHealth,
+    /// Requests currently in flight on this backend.
+    outstanding: usize,
+    breaker: BreakerStatus,
+}
 
 /// Connection with the Cogito agent.
 ///
-/// Cloning this is cheap due to it just being a channel.
+/// The agent may run as several replicas, each of which is a backend in this pool. Requests are
+/// balanced between healthy backends and fail over to the next backend when one is unavailable.
+/// Every backend has a circuit breaker, so a struggling agent is given time to recover instead of
+/// being hammered with requests. Backends connect lazily, so the API can start and serve
+/// everything that doesn't need the agent while it is down.
+///
+/// Cloning this is cheap due to it just being a reference to the shared pool.
 #[derive(Clone)]
 pub struct CogitoAgent {
-    client: CogitoClient<tonic::transport::Channel>,
+    pool: Arc<AgentPool>,
+}
+
+struct AgentPool {
+    backends: Vec<Arc<AgentBackend>>,
+    balancing: Balancing,
+    /// Rotates the starting backend between requests.
+    next: AtomicUsize,
+    timeout: Duration,
+    max_retries: u32,
+    retry_backoff: Duration,
+    /// Sent as the `authorization` metadata of every call.
+    authorization: Option<AsciiMetadataValue>,
+    capabilities_ttl: Duration,
+    /// The last capabilities reported by the agent, or why fetching them failed, and when.
+    capabilities: Mutex<Option<(Instant, Result<Capabilities, Status>)>>,
+}
+
+/// Deadline for fetching the agent's capabilities, including retries.
+const CAPABILITIES_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a failure to fetch the agent's capabilities is cached.
+const CAPABILITIES_FAILURE_TTL: Duration = Duration::from_secs(10);
+
+/// Whether a failed call may be retried, possibly on another backend.
+fn is_retryable(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+/// Whether a failed question may be asked again, possibly on another backend.
+///
+/// Research is expensive and may act on the user's behalf, so a question is only asked again if
+/// the backend couldn't be reached. `Aborted` or `ResourceExhausted` may come after the agent
+/// already started on it.
+fn is_question_retryable(code: Code) -> bool {
+    code == Code::Unavailable
+}
+
+/// Whether a failed call says something is wrong with the backend rather than the request.
+fn is_backend_failure(code: Code) -> bool {
+    // A deadline expiring on our side is reported as `Cancelled`.
+    is_retryable(code)
+        || matches!(
+            code,
+            Code::DeadlineExceeded | Code::Cancelled | Code::Internal | Code::Unknown
+        )
 }
 
 impl CogitoAgent {
-    pub async fn connect(url: String) -> Result<Self, Box<dyn Error>> {
+    /// Set up a pool of agent backends without waiting for any of them to be reachable.
+    ///
+    /// This only fails if no URLs are given, one of them is invalid or the TLS configuration can't
+    /// be loaded.
+    pub fn connect_lazy(config: AgentConfig) -> Result<Self, Box<dyn Error>> {
+        if config.urls.is_empty() {
+            return Err("At least one Cogito agent URL is required.".into());
+        }
+
+        let tls = config
+            .tls
+            .as_ref()
+            .map(AgentTls::client_config)
+            .transpose()?;
+
+        let authorization: Option<AsciiMetadataValue> = match &config.token {
+            Some(token) => Some(format!("Bearer {}", token).parse().map_err(
+                |_| "The Cogito agent token may only contain visible ASCII characters.",
+            )?),
+            None => None,
+        };
+
+        let backends = config
+            .urls
+            .into_iter()
+            .map(|url| {
+                AgentBackend::connect_lazy(
+                    url,
+                    tls.clone(),
+                    CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown),
+                    config.health_interval,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         Ok(CogitoAgent {
-            client: CogitoClient::connect(url).await?,
+            pool: Arc::new(AgentPool {
+                backends,
+                balancing: config.balancing,
+                next: AtomicUsize::new(0),
+                timeout: config.timeout,
+                max_retries: config.max_retries,
+                retry_backoff: config.retry_backoff,
+                authorization,
+                capabilities_ttl: config.capabilities_ttl,
+                capabilities: Mutex::new(None),
+            }),
+        })
+    }
+
+    /// Order backends by preference for the next request.
+    ///
+    /// Healthy backends come first, ordered by the balancing strategy. Unhealthy backends are kept
+    /// at the end as a last resort, since they may have recovered without us noticing yet. A pinned
+    /// backend is the only candidate.
+    fn candidates(&self, pinned: Option<&str>) -> Vec<Arc<AgentBackend>> {
+        let backends = &self.pool.backends;
+
+        if let Some(pinned) = pinned {
+            return backends
+                .iter()
+                .filter(|backend| backend.url == pinned)
+                .cloned()
+                .collect();
+        }
+
+        let start = self.pool.next.fetch_add(1, Ordering::Relaxed) % backends.len();
+
+        let mut ordered: Vec<Arc<AgentBackend>> = (0..backends.len())
+            .map(|i| Arc::clone(&backends[(start + i) % backends.len()]))
+            .collect();
+
+        // Both sorts are stable, so ties keep the round robin order.
+        if self.pool.balancing == Balancing::LeastOutstanding {
+            ordered.sort_by_key(|backend| backend.outstanding());
+        }
+        ordered.sort_by_key(|backend| !backend.is_healthy());
+
+        ordered
+    }
+
+    /// Report the state of every backend.
+    pub fn status(&self) -> Vec<BackendStatus> {
+        self.pool
+            .backends
+            .iter()
+            .map(|backend| BackendStatus {
+                url: backend.url.clone(),
+                healthy: backend.is_healthy(),
+                health: backend.health(),
+                outstanding: backend.outstanding(),
+                breaker: backend.breaker.status(),
+            })
+            .collect()
+    }
+
+    /// Deadline for a single call, including a stream of progress.
+    pub fn timeout(&self) -> Duration {
+        self.pool.timeout
+    }
+
+    /// Whether a backend with this URL is configured, so conversations can be pinned to it.
+    pub fn has_backend(&self, url: &str) -> bool {
+        self.pool.backends.iter().any(|backend| backend.url == url)
+    }
+
+    /// Whether any backend is healthy and accepting requests.
+    pub fn is_ready(&self) -> bool {
+        self.pool
+            .backends
+            .iter()
+            .any(|backend| backend.is_healthy() && backend.breaker.status() != BreakerStatus::Open)
+    }
+
+    /// Build a request carrying the API's credentials.
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.set_timeout(self.pool.timeout);
+
+        if let Some(authorization) = &self.pool.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+
+        request
+    }
+
+    /// Make a call to the agent, balancing, failing over and retrying as configured.
+    ///
+    /// Every attempt tries the backends in order of preference, skipping those whose circuit
+    /// breaker is open and failing over on retryable errors. If every backend failed in a way that
+    /// can be retried, the whole attempt is retried after a jittered exponential backoff. Any other
+    /// error is returned immediately. When every breaker is open this fails fast.
+    ///
+    /// With a `pinned` backend URL only that backend is tried, although retries still apply.
+    /// `retryable` decides which errors are retried, since not every call is safe to repeat.
+    ///
+    /// Also returns the backend that answered, for calls that must go back to the same one. It
+    /// counts as busy until that is dropped, so keep it around while reading a stream.
+    async fn call<T, F, Fut>(
+        &self,
+        pinned: Option<&str>,
+        retryable: fn(Code) -> bool,
+        mut call_backend: F,
+    ) -> Result<(T, OutstandingRequest), Status>
+    where
+        F: FnMut(CogitoClient<Channel>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, Status>>,
+    {
+        if let Some(pinned) = pinned
+            && !self.has_backend(pinned)
+        {
+            return Err(Status::failed_precondition(format!(
+                "The Cogito agent backend {} is no longer configured.",
+                pinned
+            )));
+        }
+
+        let mut last_error = Status::unavailable("No Cogito agent backend is available.");
+
+        for attempt in 0..=self.pool.max_retries {
+            if attempt > 0 {
+                let backoff = self.pool.retry_backoff * 2u32.saturating_pow(attempt - 1);
+                let jitter = rand::thread_rng().gen_range(0.5..1.5);
+                tokio::time::sleep(backoff.mul_f64(jitter)).await;
+            }
+
+            let mut attempted = false;
+
+            for backend in self.candidates(pinned) {
+                if !backend.breaker.allow() {
+                    continue;
+                }
+                attempted = true;
+
+                let outstanding = backend.start_request();
+
+                match call_backend(backend.client()).await {
+                    Ok(response) => {
+                        backend.breaker.record_success();
+                        return Ok((response.into_inner(), outstanding));
+                    }
+                    Err(status) => {
+                        if is_backend_failure(status.code()) {
+                            backend.breaker.record_failure();
+                        } else {
+                            // The backend is fine, it just didn't like the request.
+                            backend.breaker.record_success();
+                        }
+                        if status.code() == Code::Unavailable {
+                            backend.mark_disconnected();
+                        }
+                        if !retryable(status.code()) {
+                            return Err(status);
+                        }
+
+                        warn!(
+                            "Cogito agent backend {} failed, failing over: {}",
+                            backend.url,
+                            status.message()
+                        );
+                        last_error = status;
+                    }
+                }
+            }
+
+            // Every breaker is open, don't wait around for them.
+            if !attempted {
+                return Err(Status::unavailable(
+                    "Every Cogito agent backend is temporarily disabled.",
+                ));
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Ask the agent a question.
+    pub async fn ask(&self, question: Question, context: &CallContext) -> Result<Answer, Status> {
+        self.call(
+            context.backend.as_deref(),
+            is_question_retryable,
+            |mut client| {
+                let mut request = self.request(question.clone());
+                context.write_metadata(request.metadata_mut());
+                async move { client.ask(request).await }
+            },
+        )
+        .await
+        .map(|(answer, _)| answer)
+    }
+
+    /// Ask the agent a question, calling `on_event` for everything it reports while researching.
+    ///
+    /// Only opening the stream is balanced and retried, a stream that breaks off fails the call.
+    /// Agents without progress reporting are asked the plain way.
+    pub async fn ask_with_progress(
+        &self,
+        question: Question,
+        context: &CallContext,
+        mut on_event: impl FnMut(AgentEvent),
+    ) -> Result<Answer, Status> {
+        let opened = self
+            .call(
+                context.backend.as_deref(),
+                is_question_retryable,
+                |mut client| {
+                    let mut request = self.request(question.clone());
+                    context.write_metadata(request.metadata_mut());
+                    async move { client.ask_with_progress(request).await }
+                },
+            )
+            .await;
+
+        // Keeps the backend counted as busy until the stream is done.
+        let (mut stream, outstanding) = match opened {
+            Ok(opened) => opened,
+            Err(status) if status.code() == Code::Unimplemented => {
+                return self.ask(question, context).await;
+            }
+            Err(status) => return Err(status),
+        };
+
+        while let Some(event) = stream.message().await? {
+            match event.event {
+                Some(ask_event::Event::Progress(progress)) => {
+                    on_event(AgentEvent::Progress(progress))
+                }
+                Some(ask_event::Event::ApprovalRequest(request)) => {
+                    on_event(AgentEvent::ApprovalRequested {
+                        request,
+                        backend: outstanding.backend().url.clone(),
+                    })
+                }
+                Some(ask_event::Event::Answer(answer)) => return Ok(answer),
+                None => {}
+            }
+        }
+
+        Err(Status::internal(
+            "The Cogito agent ended the stream without an answer.",
+        ))
+    }
+
+    /// What the agent supports, cached for the configured TTL.
+    ///
+    /// Replicas are expected to run the same version, so whichever backend answers first speaks
+    /// for all of them. Failures are cached for a few seconds, so an agent that is down doesn't
+    /// hold up every request that asks for its capabilities.
+    pub async fn capabilities(&self) -> Result<Capabilities, Status> {
+        // Not held while fetching, a slow agent would otherwise queue up every caller behind it.
+        if let Some((fetched_at, capabilities)) = &*self.pool.capabilities.lock().unwrap() {
+            let ttl = match capabilities {
+                Ok(_) => self.pool.capabilities_ttl,
+                Err(_) => CAPABILITIES_FAILURE_TTL,
+            };
+            if fetched_at.elapsed() < ttl {
+                return capabilities.clone();
+            }
+        }
+
+        let deadline = CAPABILITIES_TIMEOUT.min(self.pool.timeout);
+        let fetched = tokio::time::timeout(
+            deadline,
+            self.call(None, is_retryable, |mut client| {
+                let mut request = self.request(CapabilitiesRequest {});
+                request.set_timeout(deadline);
+                async move { client.get_capabilities(request).await }
+            }),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(Status::deadline_exceeded(
+                "The Cogito agent didn't report its capabilities in time.",
+            ))
+        })
+        .map(|(capabilities, _)| capabilities);
+
+        *self.pool.capabilities.lock().unwrap() = Some((Instant::now(), fetched.clone()));
+        fetched
+    }
+
+    /// Have the agent condense earlier turns of a conversation.
+    pub async fn summarize(&self, request: SummaryRequest) -> Result<String, Status> {
+        self.call(None, is_retryable, |mut client| {
+            let request = self.request(request.clone());
+            async move { client.summarize(request).await }
+        })
+        .await
+        .map(|(summary, _)| summary.content)
+    }
+
+    /// Tell the agent whether an action it asked approval for may go ahead.
+    ///
+    /// This goes to `backend`, the one waiting for the decision, without failing over.
+    pub async fn resolve_action(
+        &self,
+        backend: &str,
+        decision: ActionDecision,
+    ) -> Result<(), Status> {
+        let Some(backend) = self
+            .pool
+            .backends
+            .iter()
+            .find(|candidate| candidate.url == backend)
+        else {
+            return Err(Status::not_found(
+                "The Cogito agent backend is no longer configured.",
+            ));
+        };
+
+        backend
+            .client()
+            .resolve_action(self.request(decision))
+            .await
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Connect a pool to backends that are never reached.
+    fn pool(balancing: Balancing) -> CogitoAgent {
+        CogitoAgent::connect_lazy(AgentConfig {
+            urls: vec![
+                "127.0.0.1:1".to_string(),
+                "127.0.0.1:2".to_string(),
+                "127.0.0.1:3".to_string(),
+            ],
+            balancing,
+            ..AgentConfig::default()
         })
+        .unwrap()
+    }
+
+    fn urls(candidates: Vec<Arc<AgentBackend>>) -> Vec<String> {
+        candidates
+            .iter()
+            .map(|backend| backend.url.clone())
+            .collect()
+    }
+
+    /// Test that round robin starts at the next backend on every request.
+    #[tokio::test]
+    async fn test_round_robin() {
+        let agent = pool(Balancing::RoundRobin);
+        let _busy = agent.pool.backends[0].start_request();
+
+        assert_eq!(
+            urls(agent.candidates(None)),
+            ["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"]
+        );
+        assert_eq!(
+            urls(agent.candidates(None)),
+            ["127.0.0.1:2", "127.0.0.1:3", "127.0.0.1:1"]
+        );
+    }
+
+    /// Test that the backend with the fewest requests in flight is preferred, taking turns on ties.
+    #[tokio::test]
+    async fn test_least_outstanding() {
+        let agent = pool(Balancing::LeastOutstanding);
+        let busy = [
+            agent.pool.backends[0].start_request(),
+            agent.pool.backends[0].start_request(),
+            agent.pool.backends[1].start_request(),
+        ];
+
+        assert_eq!(
+            urls(agent.candidates(None)),
+            ["127.0.0.1:3", "127.0.0.1:2", "127.0.0.1:1"]
+        );
+
+        let _busier = agent.pool.backends[2].start_request();
+        assert_eq!(
+            urls(agent.candidates(None)),
+            ["127.0.0.1:2", "127.0.0.1:3", "127.0.0.1:1"]
+        );
+
+        // Finished requests no longer count.
+        drop(busy);
+        assert_eq!(agent.pool.backends[0].outstanding(), 0);
+        let rounds: Vec<Vec<String>> = (0..3).map(|_| urls(agent.candidates(None))).collect();
+        assert!(rounds.iter().all(|round| round[2] == "127.0.0.1:3"));
+        assert!(rounds.iter().any(|round| round[0] == "127.0.0.1:1"));
+        assert!(rounds.iter().any(|round| round[0] == "127.0.0.1:2"));
+    }
+
+    /// Test that a pinned conversation only goes to its own backend.
+    #[tokio::test]
+    async fn test_pinned_backend() {
+        let agent = pool(Balancing::LeastOutstanding);
+        let _busy = agent.pool.backends[1].start_request();
+
+        assert_eq!(urls(agent.candidates(Some("127.0.0.1:2"))), ["127.0.0.1:2"]);
+        assert!(agent.candidates(Some("127.0.0.1:4")).is_empty());
     }
 
-    pub fn get_client(&self) -> CogitoClient<tonic::transport::Channel> {
-        // It is cheap to clone here.
-        self.client.clone()
+    /// Test that questions are only asked again if the agent never got them.
+    #[test]
+    fn test_question_retries() {
+        assert!(is_question_retryable(Code::Unavailable));
+        assert!(!is_question_retryable(Code::Aborted));
+        assert!(!is_question_retryable(Code::ResourceExhausted));
+        assert!(is_retryable(Code::Aborted));
+        assert!(is_retryable(Code::ResourceExhausted));
     }
 }
@@ -0,0 +1,197 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::agent::breaker::CircuitBreaker;
+use crate::agent::health::{AgentHealth, poll_health};
+use crate::proto::cogito_client::CogitoClient;
+use log::{info, warn};
+use tokio::sync::Notify;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+/// Delay before the first reconnection attempt after losing a backend.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Upper bound for the delay between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long to wait for a backend to accept a connection before giving up on a request.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single Cogito agent replica.
+///
+/// The connection is made lazily and kept alive by a background task which reconnects with
+/// exponential backoff whenever the backend can't be reached.
+pub(super) struct AgentBackend {
+    /// The address this backend was configured with, used to identify it in logs.
+    pub(super) url: String,
+    endpoint: Endpoint,
+    channel: RwLock<Channel>,
+    connected: AtomicBool,
+    /// Woken up when a request notices the backend went away.
+    disconnected: Notify,
+    /// Requests currently in flight on this backend.
+    outstanding: AtomicUsize,
+    /// Result of the latest health check.
+    health: Mutex<AgentHealth>,
+    pub(super) breaker: CircuitBreaker,
+}
+
+/// Counts a request as outstanding on a backend for as long as it is alive.
+pub(super) struct OutstandingRequest(Arc<AgentBackend>);
+
+impl OutstandingRequest {
+    /// The backend handling the request.
+    pub(super) fn backend(&self) -> &AgentBackend {
+        &self.0
+    }
+}
+
+impl Drop for OutstandingRequest {
+    fn drop(&mut self) {
+        self.0.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AgentBackend {
+    /// Set up a backend at `url` without waiting for it to be reachable.
+    ///
+    /// This only fails if `url` or the TLS configuration is invalid. Must be called from within a
+    /// Tokio runtime since it spawns the reconnection and health checking tasks.
+    pub(super) fn connect_lazy(
+        url: String,
+        tls: Option<ClientTlsConfig>,
+        breaker: CircuitBreaker,
+        health_interval: Duration,
+    ) -> Result<Arc<Self>, Box<dyn Error>> {
+        // Allow plain `host:port` addresses like the ones in `.env.example`.
+        let uri = if url.contains("://") {
+            url.clone()
+        } else if tls.is_some() {
+            format!("https://{}", url)
+        } else {
+            format!("http://{}", url)
+        };
+
+        let mut endpoint = Endpoint::from_shared(uri.clone())?.connect_timeout(CONNECT_TIMEOUT);
+
+        if let Some(tls) = tls {
+            // tonic would quietly fall back to plaintext for these.
+            if !uri.starts_with("https://") {
+                return Err(format!(
+                    "Cogito agent backend {} must use https:// when TLS is configured.",
+                    url
+                )
+                .into());
+            }
+
+            endpoint = endpoint.tls_config(tls).map_err(|e| {
+                format!(
+                    "Invalid TLS configuration for Cogito agent backend {}: {}",
+                    url, e
+                )
+            })?;
+        } else if uri.starts_with("https://") {
+            return Err(format!(
+                "Cogito agent backend {} uses https:// but TLS is not configured.",
+                url
+            )
+            .into());
+        }
+
+        let backend = Arc::new(AgentBackend {
+            url,
+            channel: RwLock::new(endpoint.connect_lazy()),
+            endpoint,
+            connected: AtomicBool::new(false),
+            disconnected: Notify::new(),
+            outstanding: AtomicUsize::new(0),
+            health: Mutex::new(AgentHealth::Unknown),
+            breaker,
+        });
+
+        tokio::spawn(maintain_connection(Arc::clone(&backend)));
+        tokio::spawn(poll_health(Arc::clone(&backend), health_interval));
+
+        Ok(backend)
+    }
+
+    /// Whether the backend was reachable the last time it was used.
+    pub(super) fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Whether the backend is reachable and hasn't reported itself as not serving.
+    pub(super) fn is_healthy(&self) -> bool {
+        self.is_connected() && self.health() != AgentHealth::NotServing
+    }
+
+    pub(super) fn health(&self) -> AgentHealth {
+        *self.health.lock().unwrap()
+    }
+
+    /// Record the result of a health check, returning the previous one.
+    pub(super) fn set_health(&self, health: AgentHealth) -> AgentHealth {
+        std::mem::replace(&mut *self.health.lock().unwrap(), health)
+    }
+
+    pub(super) fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn channel(&self) -> Channel {
+        // It is cheap to clone here.
+        self.channel.read().unwrap().clone()
+    }
+
+    pub(super) fn client(&self) -> CogitoClient<Channel> {
+        CogitoClient::new(self.channel())
+    }
+
+    /// Count a new request as outstanding until the returned guard is dropped.
+    pub(super) fn start_request(self: &Arc<Self>) -> OutstandingRequest {
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        OutstandingRequest(Arc::clone(self))
+    }
+
+    /// Report the backend as unreachable, waking up the reconnection task.
+    pub(super) fn mark_disconnected(&self) {
+        if self.connected.swap(false, Ordering::Relaxed) {
+            warn!("Lost connection to Cogito agent backend {}.", self.url);
+            self.disconnected.notify_one();
+        }
+    }
+}
+
+/// Keep a backend connection alive for as long as the API runs.
+///
+/// While disconnected this retries with exponential backoff, then sleeps until a request reports
+/// the backend unreachable again.
+async fn maintain_connection(backend: Arc<AgentBackend>) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        if backend.connected.load(Ordering::Relaxed) {
+            backend.disconnected.notified().await;
+            continue;
+        }
+
+        match backend.endpoint.connect().await {
+            Ok(channel) => {
+                *backend.channel.write().unwrap() = channel;
+                backend.connected.store(true, Ordering::Relaxed);
+                backoff = INITIAL_BACKOFF;
+                info!("Connected to Cogito agent backend {}.", backend.url);
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to reach Cogito agent backend {}, retrying in {:?}: {}",
+                    backend.url, backoff, e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Circuit breaker guarding a single agent backend.
+///
+/// After `threshold` consecutive failures the breaker opens and requests to the backend fail fast
+/// for `cooldown`. After that a single trial request is let through; if it succeeds the breaker
+/// closes again, otherwise it reopens for another cooldown.
+pub(super) struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A trial request was let through at `since`.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// Breaker state as reported for monitoring.
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerStatus {
+    /// Requests flow normally.
+    Closed,
+    /// Requests fail fast until the cooldown is over.
+    Open,
+    /// A trial request is deciding whether to close the breaker.
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    pub(super) fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            threshold: threshold.max(1),
+            cooldown,
+        }
+    }
+
+    /// Whether a request may be sent to the backend right now.
+    pub(super) fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::Open { .. } => false,
+            // A trial that never reported back (e.g. the request was dropped) shouldn't keep the
+            // breaker half open forever.
+            BreakerState::HalfOpen { since } if now >= since + self.cooldown => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    pub(super) fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    pub(super) fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        *state = match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            _ => BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            },
+        };
+    }
+
+    pub(super) fn status(&self) -> BreakerStatus {
+        match *self.state.lock().unwrap() {
+            BreakerState::Closed { .. } => BreakerStatus::Closed,
+            BreakerState::Open { .. } => BreakerStatus::Open,
+            BreakerState::HalfOpen { .. } => BreakerStatus::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    /// Test that the breaker only opens after `threshold` consecutive failures.
+    #[test]
+    fn test_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.status() == BreakerStatus::Closed);
+        assert!(breaker.allow());
+
+        // A success in between starts the count over.
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.status() == BreakerStatus::Closed);
+
+        breaker.record_failure();
+        assert!(breaker.status() == BreakerStatus::Open);
+        assert!(!breaker.allow());
+    }
+
+    /// Test that a single trial request is let through after the cooldown and closes the breaker.
+    #[test]
+    fn test_half_open_trial_closes() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        assert!(!breaker.allow());
+
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow());
+        assert!(breaker.status() == BreakerStatus::HalfOpen);
+        assert!(!breaker.allow());
+
+        breaker.record_success();
+        assert!(breaker.status() == BreakerStatus::Closed);
+        assert!(breaker.allow());
+    }
+
+    /// Test that a failed trial request reopens the breaker for another cooldown.
+    #[test]
+    fn test_half_open_trial_reopens() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow());
+
+        // The trial alone reopens it, no need for another `threshold` failures.
+        breaker.record_failure();
+        assert!(breaker.status() == BreakerStatus::Open);
+        assert!(!breaker.allow());
+    }
+
+    /// Test that a trial which never reports back doesn't keep the breaker half open forever.
+    #[test]
+    fn test_abandoned_trial() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.allow());
+        assert!(breaker.status() == BreakerStatus::HalfOpen);
+    }
+}
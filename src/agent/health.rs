@@ -0,0 +1,80 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::agent::backend::AgentBackend;
+use log::{info, warn};
+use serde::Serialize;
+use tonic::Code;
+use tonic_health::pb::HealthCheckRequest;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use utoipa::ToSchema;
+
+/// Service name the agent reports its health under, as in `grpc.health.v1`.
+const COGITO_SERVICE: &str = "cogito.Cogito";
+
+/// How long a health check may take before the backend counts as not serving.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health of a backend according to its `grpc.health.v1` service.
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentHealth {
+    /// Not checked yet, not connected, or the agent doesn't implement health checks.
+    Unknown,
+    Serving,
+    NotServing,
+}
+
+/// Check a backend's health every `interval` for as long as the API runs.
+///
+/// Only connected backends are checked, reconnecting is left to the connection task. A backend
+/// reporting anything but serving is avoided by the balancer until it recovers.
+pub(super) async fn poll_health(backend: Arc<AgentBackend>, interval: Duration) {
+    loop {
+        let health = if backend.is_connected() {
+            check(&backend).await
+        } else {
+            AgentHealth::Unknown
+        };
+
+        let previous = backend.set_health(health);
+        if previous != health {
+            match health {
+                AgentHealth::Serving => {
+                    info!("Cogito agent backend {} is serving.", backend.url)
+                }
+                AgentHealth::NotServing => {
+                    warn!("Cogito agent backend {} is not serving.", backend.url)
+                }
+                AgentHealth::Unknown => {}
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn check(backend: &AgentBackend) -> AgentHealth {
+    let mut client = HealthClient::new(backend.channel());
+
+    let mut request = tonic::Request::new(HealthCheckRequest {
+        service: COGITO_SERVICE.to_string(),
+    });
+    request.set_timeout(HEALTH_CHECK_TIMEOUT);
+
+    match client.check(request).await {
+        Ok(response) if response.get_ref().status() == ServingStatus::Serving => {
+            AgentHealth::Serving
+        }
+        Ok(_) => AgentHealth::NotServing,
+        // Older agents don't have a health service, only requests can tell if they work.
+        Err(status) if status.code() == Code::Unimplemented => AgentHealth::Unknown,
+        Err(status) => {
+            if status.code() == Code::Unavailable {
+                backend.mark_disconnected();
+            }
+            AgentHealth::NotServing
+        }
+    }
+}
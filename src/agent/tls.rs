@@ -0,0 +1,67 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+/// TLS settings for connections to the agent.
+///
+/// Without a CA bundle the system's trusted roots are used. Giving a client certificate and key
+/// enables mutual TLS, so the agent can verify it is talking to the API.
+#[derive(Default)]
+pub struct AgentTls {
+    /// PEM bundle of the certificate authorities trusted to sign the agent's certificate.
+    pub ca_certificate: Option<PathBuf>,
+    /// PEM certificate presented to the agent for mutual TLS.
+    pub client_certificate: Option<PathBuf>,
+    /// PEM private key for `client_certificate`.
+    pub client_key: Option<PathBuf>,
+    /// Name to verify the agent's certificate against instead of the host in its URL.
+    pub domain: Option<String>,
+}
+
+/// Read a PEM file, naming what it is for if that fails.
+fn read_pem(path: &PathBuf, what: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    std::fs::read(path).map_err(|e| {
+        format!(
+            "Unable to read Cogito agent {} `{}`: {}",
+            what,
+            path.display(),
+            e
+        )
+        .into()
+    })
+}
+
+impl AgentTls {
+    /// Load the certificates and build the TLS configuration shared by every backend.
+    pub(super) fn client_config(&self) -> Result<ClientTlsConfig, Box<dyn Error>> {
+        let mut config = ClientTlsConfig::new();
+
+        config = match &self.ca_certificate {
+            Some(path) => {
+                config.ca_certificate(Certificate::from_pem(read_pem(path, "CA bundle")?))
+            }
+            None => config.with_native_roots(),
+        };
+
+        config = match (&self.client_certificate, &self.client_key) {
+            (Some(certificate), Some(key)) => config.identity(Identity::from_pem(
+                read_pem(certificate, "client certificate")?,
+                read_pem(key, "client key")?,
+            )),
+            (None, None) => config,
+            _ => {
+                return Err(
+                    "Mutual TLS with the Cogito agent needs both a client certificate and key."
+                        .into(),
+                );
+            }
+        };
+
+        if let Some(domain) = &self.domain {
+            config = config.domain_name(domain.clone());
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use crate::agent::{BackendStatus, CogitoAgent};
+use crate::api_messages::{AGENT_FAILED_TO_COMMUNICATE, BAD_SESSION, GenericResponse};
+use crate::login::validate_session;
+use crate::proto;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, Responder, get};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+/// JSON response describing the state of the agent connection.
+#[derive(Serialize, ToSchema)]
+pub struct AgentStatusResponse {
+    backends: Vec<BackendStatus>,
+}
+
+/// What the agent supports, for building settings UIs.
+#[derive(Serialize, ToSchema)]
+pub struct CapabilitiesResponse {
+    agent_version: String,
+    models: Vec<AgentModel>,
+    /// Collections of sources the agent can search.
+    corpora: Vec<AgentCorpus>,
+    tools: Vec<AgentTool>,
+    /// Numeric settings and their allowed ranges.
+    options: Vec<AgentOptionRange>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AgentModel {
+    id: String,
+    name: String,
+    description: String,
+    /// Whether this model is used when none is picked.
+    is_default: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AgentCorpus {
+    id: String,
+    name: String,
+    description: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AgentTool {
+    id: String,
+    name: String,
+    description: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AgentOptionRange {
+    name: String,
+    description: String,
+    min: f64,
+    max: f64,
+    default_value: f64,
+    /// Whether only whole numbers are allowed.
+    integer: bool,
+}
+
+/// Settings for how the agent answers a question, chosen from `GET /agent/capabilities`.
+///
+/// Everything is optional, the agent falls back to its defaults.
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default)]
+pub struct AgentOptions {
+    /// ID of the model to answer with.
+    model: Option<String>,
+    /// IDs of the corpora to search, all of them if empty.
+    corpora: Vec<String>,
+    /// IDs of the tools the agent may use, all of them if empty.
+    tools: Vec<String>,
+    /// Values for the ranged options, by name.
+    parameters: HashMap<String, f64>,
+}
+
+impl From<AgentOptions> for proto::AskOptions {
+    fn from(options: AgentOptions) -> Self {
+        proto::AskOptions {
+            model: options.model.unwrap_or_default(),
+            corpora: options.corpora,
+            tools: options.tools,
+            parameters: options.parameters,
+        }
+    }
+}
+
+impl From<proto::Capabilities> for CapabilitiesResponse {
+    fn from(capabilities: proto::Capabilities) -> Self {
+        CapabilitiesResponse {
+            agent_version: capabilities.agent_version,
+            models: capabilities
+                .models
+                .into_iter()
+                .map(|model| AgentModel {
+                    id: model.id,
+                    name: model.name,
+                    description: model.description,
+                    is_default: model.is_default,
+                })
+                .collect(),
+            corpora: capabilities
+                .corpora
+                .into_iter()
+                .map(|corpus| AgentCorpus {
+                    id: corpus.id,
+                    name: corpus.name,
+                    description: corpus.description,
+                })
+                .collect(),
+            tools: capabilities
+                .tools
+                .into_iter()
+                .map(|tool| AgentTool {
+                    id: tool.id,
+                    name: tool.name,
+                    description: tool.description,
+                })
+                .collect(),
+            options: capabilities
+                .options
+                .into_iter()
+                .map(|option| AgentOptionRange {
+                    name: option.name,
+                    description: option.description,
+                    min: option.min,
+                    max: option.max,
+                    default_value: option.default_value,
+                    integer: option.integer,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Report the health, load, and circuit breaker state of every agent backend.
+///
+/// This reveals the internal URLs of the backends, so it requires a session. Unauthenticated
+/// monitoring should use `/readyz` instead.
+#[utoipa::path(
+    get,
+    path = "/agent/status",
+    responses(
+        (status = 200, description = "Agent status retrieved successfully.", body = AgentStatusResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+    )
+)]
+#[get("/agent/status")]
+pub async fn agent_status(
+    req: HttpRequest,
+    cogito_agent: Data<CogitoAgent>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    if let Err(e) = validate_session(&req, db.get_ref()).await {
+        return e;
+    }
+
+    HttpResponse::Ok().json(AgentStatusResponse {
+        backends: cogito_agent.status(),
+    })
+}
+
+/// Report the models, corpora, tools, and options the agent supports.
+///
+/// The answer is cached, so this is cheap to call whenever a settings UI is shown.
+#[utoipa::path(
+    get,
+    path = "/agent/capabilities",
+    responses(
+        (status = 200, description = "Agent capabilities retrieved successfully.", body = CapabilitiesResponse),
+        (status = 500, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+        (status = 503, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+    )
+)]
+#[get("/agent/capabilities")]
+pub async fn agent_capabilities(cogito_agent: Data<CogitoAgent>) -> impl Responder {
+    match cogito_agent.capabilities().await {
+        Ok(capabilities) => HttpResponse::Ok().json(CapabilitiesResponse::from(capabilities)),
+        Err(status) => {
+            error!(
+                "Failed to get capabilities from the Cogito agent: {}",
+                status
+            );
+
+            let mut response = if status.code() == tonic::Code::Unavailable {
+                HttpResponse::ServiceUnavailable()
+            } else {
+                HttpResponse::InternalServerError()
+            };
+            response.json(GenericResponse {
+                message: AGENT_FAILED_TO_COMMUNICATE,
+            })
+        }
+    }
+}
@@ -0,0 +1,563 @@
+use crate::api_messages::{BAD_SESSION, FORBIDDEN, GenericResponse, SERVER_ERROR};
+use crate::conversation::fetch_conversation;
+use crate::login::validate_session;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post, put};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Error, PgPool};
+use utoipa::ToSchema;
+
+// Annotations are anchored to a string inside the stored conversation JSON by a JSON pointer and a
+// character range. The highlighted text and a little context around it are stored alongside so the
+// annotation can be found again if the answer is regenerated and the offsets shift.
+//
+// Offsets are counted in characters (Unicode scalar values), not bytes.
+
+/// Amount of characters stored before and after a highlight to help re-anchor it later.
+const ANCHOR_CONTEXT_CHARS: usize = 32;
+
+/// The message returned when an annotation anchor can't be resolved against a conversation.
+static BAD_ANCHOR: &str = "Annotation anchor does not point to text in this conversation.";
+
+/// The message returned when an annotation doesn't exist or belongs to someone else.
+static ANNOTATION_NOT_FOUND: &str = "Annotation not found.";
+
+/// A private note and/or highlight attached to a range of text in an agent answer.
+#[derive(Serialize, ToSchema)]
+pub struct Annotation {
+    pub(crate) annotation_id: i32,
+    pub(crate) conversation_id: i32,
+    pub(crate) user_id: i32,
+    /// JSON pointer to the annotated string within the conversation, e.g. `/messages/1/content`.
+    pub(crate) anchor_path: String,
+    /// Character offset where the highlight starts.
+    pub(crate) anchor_start: i32,
+    /// Character offset where the highlight ends (exclusive).
+    pub(crate) anchor_end: i32,
+    /// The highlighted text.
+    pub(crate) anchor_quote: String,
+    /// Text immediately preceding the highlight.
+    pub(crate) anchor_prefix: String,
+    /// Text immediately following the highlight.
+    pub(crate) anchor_suffix: String,
+    /// Optional note attached to the highlight.
+    pub(crate) note: Option<String>,
+    /// Highlight color chosen by the user.
+    pub(crate) color: String,
+    #[schema(value_type = String, format = "date-time")]
+    pub(crate) created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = "date-time")]
+    pub(crate) updated_at: DateTime<Utc>,
+}
+
+/// An annotation along with whether it could still be located in the conversation.
+#[derive(Serialize, ToSchema)]
+pub struct AnchoredAnnotation {
+    #[serde(flatten)]
+    annotation: Annotation,
+    /// True when the highlighted text no longer exists in the conversation.
+    orphaned: bool,
+}
+
+/// Post request data to annotate part of a conversation.
+#[derive(Deserialize, ToSchema)]
+pub struct CreateAnnotationRequest {
+    /// JSON pointer to the string being annotated.
+    anchor_path: String,
+    /// Character offset where the highlight starts.
+    anchor_start: i32,
+    /// Character offset where the highlight ends (exclusive).
+    anchor_end: i32,
+    /// Optional note attached to the highlight.
+    note: Option<String>,
+    /// Highlight color, defaults to yellow.
+    color: Option<String>,
+}
+
+/// Put request data to change an existing annotation.
+///
+/// Fields that are left out are kept as they are.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateAnnotationRequest {
+    note: Option<String>,
+    color: Option<String>,
+}
+
+/// Location of a highlight within a string, along with the text needed to find it again.
+struct Anchor {
+    start: usize,
+    end: usize,
+    quote: String,
+    prefix: String,
+    suffix: String,
+}
+
+/// Build an anchor for the character range `start..end` of `text`.
+fn build_anchor(text: &str, start: usize, end: usize) -> Option<Anchor> {
+    let chars: Vec<char> = text.chars().collect();
+
+    if start >= end || end > chars.len() {
+        return None;
+    }
+
+    Some(Anchor {
+        start,
+        end,
+        quote: chars[start..end].iter().collect(),
+        prefix: chars[start.saturating_sub(ANCHOR_CONTEXT_CHARS)..start]
+            .iter()
+            .collect(),
+        suffix: chars[end..(end + ANCHOR_CONTEXT_CHARS).min(chars.len())]
+            .iter()
+            .collect(),
+    })
+}
+
+/// Locate an annotation's quote within `text`, returning the new character range.
+///
+/// If the text at the stored offsets still matches, those offsets are kept. Otherwise every
+/// occurrence of the quote is scored by how much of the stored prefix and suffix surrounds it, and
+/// the best match wins.
+fn resolve_anchor(text: &str, annotation: &Annotation) -> Option<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let quote: Vec<char> = annotation.anchor_quote.chars().collect();
+
+    if quote.is_empty() || quote.len() > chars.len() {
+        return None;
+    }
+
+    let (start, end) = (
+        annotation.anchor_start as usize,
+        annotation.anchor_end as usize,
+    );
+    if end <= chars.len() && start < end && chars[start..end] == quote[..] {
+        return Some((start, end));
+    }
+
+    let prefix: Vec<char> = annotation.anchor_prefix.chars().collect();
+    let suffix: Vec<char> = annotation.anchor_suffix.chars().collect();
+
+    (0..=chars.len() - quote.len())
+        .filter(|&i| chars[i..i + quote.len()] == quote[..])
+        .max_by_key(|&i| {
+            let before = chars[..i]
+                .iter()
+                .rev()
+                .zip(prefix.iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let after = chars[i + quote.len()..]
+                .iter()
+                .zip(suffix.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            // Prefer the earliest match when scores tie.
+            (before + after, std::cmp::Reverse(i))
+        })
+        .map(|i| (i, i + quote.len()))
+}
+
+/// Look up the string an anchor path points to in a conversation.
+fn anchored_text<'a>(conversation: &'a Value, anchor_path: &str) -> Option<&'a str> {
+    conversation.pointer(anchor_path).and_then(Value::as_str)
+}
+
+/// Get all of the user's annotations on a conversation.
+///
+/// Annotations whose text moved since they were created are re-anchored and saved with their new
+/// offsets. Annotations whose text can't be found anymore are returned as orphaned.
+#[utoipa::path(
+    get,
+    path = "/conversation/{conversation_id}/annotations",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the annotated conversation.")
+    ),
+    responses(
+        (status = 200, description = "Annotations retrieved successfully.", body = Vec<AnchoredAnnotation>),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Conversation not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+    ))]
+#[get("/conversation/{conversation_id}/annotations")]
+pub async fn get_annotations(
+    conversation_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    let annotations = match sqlx::query_as!(
+        Annotation,
+        r#"
+        select * from annotations
+        where conversation_id = $1 and user_id = $2
+        order by annotation_id
+        "#,
+        conversation.conversation_id,
+        user.user_id
+    )
+    .fetch_all(db.get_ref())
+    .await
+    {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            error!(
+                "Failed to retrieve annotations on conversation {} for user {}: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    };
+
+    let mut anchored = Vec::with_capacity(annotations.len());
+    for mut annotation in annotations {
+        let resolved = anchored_text(&conversation.conversation, &annotation.anchor_path)
+            .and_then(|text| resolve_anchor(text, &annotation));
+
+        let Some((start, end)) = resolved else {
+            anchored.push(AnchoredAnnotation {
+                annotation,
+                orphaned: true,
+            });
+            continue;
+        };
+
+        if (start as i32, end as i32) != (annotation.anchor_start, annotation.anchor_end) {
+            annotation.anchor_start = start as i32;
+            annotation.anchor_end = end as i32;
+
+            // Failing to save the new offsets is fine, they will just be resolved again next time.
+            if let Err(e) = sqlx::query!(
+                r#"
+                update annotations set anchor_start = $1, anchor_end = $2
+                where annotation_id = $3
+                "#,
+                annotation.anchor_start,
+                annotation.anchor_end,
+                annotation.annotation_id
+            )
+            .execute(db.get_ref())
+            .await
+            {
+                error!(
+                    "Failed to re-anchor annotation {}: {}",
+                    annotation.annotation_id, e
+                );
+            }
+        }
+
+        anchored.push(AnchoredAnnotation {
+            annotation,
+            orphaned: false,
+        });
+    }
+
+    HttpResponse::Ok().json(anchored)
+}
+
+/// Annotate a range of text in a conversation.
+#[utoipa::path(
+    post,
+    path = "/conversation/{conversation_id}/annotations",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the conversation to annotate.")
+    ),
+    request_body = CreateAnnotationRequest,
+    responses(
+        (status = 200, description = "Annotation created successfully.", body = Annotation),
+        (status = 400, description = BAD_ANCHOR, body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Conversation not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+    ))]
+#[post("/conversation/{conversation_id}/annotations")]
+pub async fn create_annotation(
+    conversation_id: Path<i32>,
+    req: HttpRequest,
+    info: Json<CreateAnnotationRequest>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    let info = info.into_inner();
+
+    let anchor = match (
+        usize::try_from(info.anchor_start),
+        usize::try_from(info.anchor_end),
+    ) {
+        (Ok(start), Ok(end)) => anchored_text(&conversation.conversation, &info.anchor_path)
+            .and_then(|text| build_anchor(text, start, end)),
+        _ => None,
+    };
+
+    let Some(anchor) = anchor else {
+        return HttpResponse::BadRequest().json(GenericResponse {
+            message: BAD_ANCHOR,
+        });
+    };
+
+    match sqlx::query_as!(
+        Annotation,
+        r#"
+        insert into annotations (
+            conversation_id, user_id, anchor_path, anchor_start, anchor_end,
+            anchor_quote, anchor_prefix, anchor_suffix, note, color
+        )
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9, coalesce($10, 'yellow'))
+        returning *
+        "#,
+        conversation.conversation_id,
+        user.user_id,
+        info.anchor_path,
+        anchor.start as i32,
+        anchor.end as i32,
+        anchor.quote,
+        anchor.prefix,
+        anchor.suffix,
+        info.note,
+        info.color
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(annotation) => HttpResponse::Ok().json(annotation),
+        Err(e) => {
+            error!(
+                "Failed to annotate conversation {} for user {}: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Change the note or color of an existing annotation.
+#[utoipa::path(
+    put,
+    path = "/conversation/{conversation_id}/annotations/{annotation_id}",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the annotated conversation."),
+        ("annotation_id" = i32, Path, description = "The ID of the annotation to update.")
+    ),
+    request_body = UpdateAnnotationRequest,
+    responses(
+        (status = 200, description = "Annotation updated successfully.", body = Annotation),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = ANNOTATION_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    ))]
+#[put("/conversation/{conversation_id}/annotations/{annotation_id}")]
+pub async fn update_annotation(
+    path: Path<(i32, i32)>,
+    req: HttpRequest,
+    info: Json<UpdateAnnotationRequest>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let (conversation_id, annotation_id) = path.into_inner();
+    let info = info.into_inner();
+
+    match sqlx::query_as!(
+        Annotation,
+        r#"
+        update annotations
+        set note = coalesce($1, note), color = coalesce($2, color), updated_at = $3
+        where annotation_id = $4 and conversation_id = $5 and user_id = $6
+        returning *
+        "#,
+        info.note,
+        info.color,
+        Utc::now(),
+        annotation_id,
+        conversation_id,
+        user.user_id
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(annotation) => HttpResponse::Ok().json(annotation),
+        Err(Error::RowNotFound) => HttpResponse::NotFound().json(GenericResponse {
+            message: ANNOTATION_NOT_FOUND,
+        }),
+        Err(e) => {
+            error!(
+                "Failed to update annotation {} for user {}: {}",
+                annotation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Delete an annotation.
+#[utoipa::path(
+    delete,
+    path = "/conversation/{conversation_id}/annotations/{annotation_id}",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the annotated conversation."),
+        ("annotation_id" = i32, Path, description = "The ID of the annotation to delete.")
+    ),
+    responses(
+        (status = 200, description = "Annotation deleted successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = ANNOTATION_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    ))]
+#[delete("/conversation/{conversation_id}/annotations/{annotation_id}")]
+pub async fn delete_annotation(
+    path: Path<(i32, i32)>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let (conversation_id, annotation_id) = path.into_inner();
+
+    match sqlx::query!(
+        r#"
+        delete from annotations
+        where annotation_id = $1 and conversation_id = $2 and user_id = $3
+        "#,
+        annotation_id,
+        conversation_id,
+        user.user_id
+    )
+    .execute(db.get_ref())
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::NotFound().json(GenericResponse {
+                message: ANNOTATION_NOT_FOUND,
+            })
+        }
+        Ok(_) => HttpResponse::Ok().json(GenericResponse {
+            message: "Annotation deleted successfully.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to delete annotation {} for user {}: {}",
+                annotation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Annotation, anchored_text, build_anchor, resolve_anchor};
+    use chrono::Utc;
+    use serde_json::json;
+
+    /// An annotation created on `text` for the character range `start..end`.
+    fn annotation(text: &str, start: usize, end: usize) -> Annotation {
+        let anchor = build_anchor(text, start, end).unwrap();
+
+        Annotation {
+            annotation_id: 1,
+            conversation_id: 1,
+            user_id: 1,
+            anchor_path: "/messages/1/content".to_string(),
+            anchor_start: anchor.start as i32,
+            anchor_end: anchor.end as i32,
+            anchor_quote: anchor.quote,
+            anchor_prefix: anchor.prefix,
+            anchor_suffix: anchor.suffix,
+            note: None,
+            color: "yellow".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Test that anchors count characters rather than bytes and reject empty or overlong ranges.
+    #[test]
+    fn test_build_anchor() {
+        let anchor = build_anchor("Ἀρετή is virtue", 0, 5).unwrap();
+        assert_eq!(anchor.quote, "Ἀρετή");
+        assert_eq!(anchor.prefix, "");
+        assert_eq!(anchor.suffix, " is virtue");
+
+        assert!(build_anchor("virtue", 3, 3).is_none());
+        assert!(build_anchor("virtue", 2, 7).is_none());
+    }
+
+    /// Test that unchanged text keeps the stored offsets.
+    #[test]
+    fn test_resolve_unchanged() {
+        let text = "Virtue is a mean between two vices.";
+        let annotation = annotation(text, 12, 16);
+
+        assert_eq!(resolve_anchor(text, &annotation), Some((12, 16)));
+    }
+
+    /// Test that a quote that moved is found again, picking the occurrence with matching context.
+    #[test]
+    fn test_resolve_moved() {
+        let text = "The mean is relative to us. A mean between extremes.";
+        let annotation = annotation(text, 30, 34);
+
+        let regenerated =
+            "Aristotle says: a mean between extremes, and the mean is relative to us.";
+        assert_eq!(resolve_anchor(regenerated, &annotation), Some((18, 22)));
+    }
+
+    /// Test that an annotation whose quote is gone can't be resolved.
+    #[test]
+    fn test_resolve_orphaned() {
+        let annotation = annotation("Virtue is a mean.", 12, 16);
+
+        assert_eq!(resolve_anchor("Virtue is a habit.", &annotation), None);
+        assert_eq!(resolve_anchor("", &annotation), None);
+    }
+
+    /// Test that anchor paths only resolve to strings.
+    #[test]
+    fn test_anchored_text() {
+        let conversation = json!({"messages": [{"content": "Question"}, {"content": "Answer"}]});
+
+        assert_eq!(
+            anchored_text(&conversation, "/messages/1/content"),
+            Some("Answer")
+        );
+        assert_eq!(anchored_text(&conversation, "/messages/1"), None);
+        assert_eq!(anchored_text(&conversation, "/messages/2/content"), None);
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use crate::context::AppliedContext;
+use crate::proto::{self, Answer};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// The agent answers with a JSON document in `Answer.content`. This module is the schema for that
+// document. Every answer is validated against it before anything is stored, so a misbehaving agent
+// can't put garbage into the database or crash the request.
+
+/// Version of the answer protocol this API understands.
+///
+/// The agent reports its version in `Answer.protocol_version`.
+pub const ANSWER_PROTOCOL_VERSION: u32 = 1;
+
+/// The version reported by agents that predate versioning, as 0 is the protobuf default.
+///
+/// Their answers use the same schema, just without citations, so they are still accepted.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 0;
+
+/// Whether this API understands answers of the given protocol version.
+pub fn is_supported_version(version: u32) -> bool {
+    version == ANSWER_PROTOCOL_VERSION || version == LEGACY_PROTOCOL_VERSION
+}
+
+/// A conversation with Cogito as produced by the agent.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AgentConversation {
+    pub messages: Vec<AgentMessage>,
+}
+
+/// A single message in a conversation.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AgentMessage {
+    pub role: Role,
+    pub content: String,
+    /// Sources the agent relied on for this message.
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    /// How the agent arrived at this message. Filled in by the API, not the agent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<ResearchStep>,
+    /// What produced this message. Filled in by the API, not the agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// How the history was cut down for the question this answers. Filled in by the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<AppliedContext>,
+}
+
+/// Who wrote a message.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A source cited by the agent.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct Citation {
+    /// Where the source comes from, e.g. "Stanford Encyclopedia of Philosophy".
+    pub source: String,
+    pub title: Option<String>,
+    /// Location within the source, e.g. "Groundwork §2".
+    pub locator: Option<String>,
+    pub url: Option<String>,
+}
+
+/// The system that produced an answer, so it can be cited exactly.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct Provenance {
+    /// Version of the agent deployment, e.g. "2.3.1".
+    pub agent_version: String,
+    /// ID of the model the agent answered with.
+    pub model: String,
+    /// URL of the agent backend the conversation is pinned to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+}
+
+impl Provenance {
+    /// Take the provenance reported with an answer.
+    ///
+    /// Agents that predate reporting it leave it empty, in which case there is nothing to record.
+    pub fn of(answer: &Answer, backend: Option<String>) -> Option<Self> {
+        if answer.agent_version.is_empty() {
+            return None;
+        }
+
+        Some(Provenance {
+            agent_version: answer.agent_version.clone(),
+            model: answer.model.clone(),
+            backend,
+        })
+    }
+}
+
+/// A step the agent took while researching an answer.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct ResearchStep {
+    pub stage: ResearchStage,
+    /// What the agent did, e.g. "reading Kant, Groundwork §2".
+    pub description: String,
+    /// The source searched or read, if any.
+    pub source: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ResearchStage {
+    Other,
+    Searching,
+    Reading,
+    Drafting,
+}
+
+impl From<proto::Progress> for ResearchStep {
+    fn from(progress: proto::Progress) -> Self {
+        let stage = match progress.stage() {
+            proto::ResearchStage::Searching => ResearchStage::Searching,
+            proto::ResearchStage::Reading => ResearchStage::Reading,
+            proto::ResearchStage::Drafting => ResearchStage::Drafting,
+            proto::ResearchStage::Unspecified => ResearchStage::Other,
+        };
+
+        ResearchStep {
+            stage,
+            description: progress.description,
+            source: Some(progress.source).filter(|source| !source.is_empty()),
+        }
+    }
+}
+
+/// Reasons an answer from the agent is rejected.
+#[derive(Debug)]
+pub enum AnswerError {
+    /// The agent speaks a different version of the answer protocol.
+    IncompatibleVersion(u32),
+    /// The answer isn't valid JSON or doesn't match the schema.
+    Malformed(serde_json::Error),
+    /// The answer matches the schema but makes no sense.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for AnswerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnswerError::IncompatibleVersion(version) => write!(
+                f,
+                "agent speaks answer protocol version {}, expected {}",
+                version, ANSWER_PROTOCOL_VERSION
+            ),
+            AnswerError::Malformed(e) => write!(f, "malformed answer: {}", e),
+            AnswerError::Invalid(reason) => write!(f, "invalid answer: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for AnswerError {}
+
+/// Parse and validate an answer from the agent.
+pub fn parse_answer(answer: &Answer) -> Result<AgentConversation, AnswerError> {
+    if !is_supported_version(answer.protocol_version) {
+        return Err(AnswerError::IncompatibleVersion(answer.protocol_version));
+    }
+
+    let conversation: AgentConversation =
+        serde_json::from_str(&answer.content).map_err(AnswerError::Malformed)?;
+
+    if conversation
+        .messages
+        .iter()
+        .any(|message| message.content.trim().is_empty())
+    {
+        return Err(AnswerError::Invalid("message without content"));
+    }
+
+    match conversation.messages.last() {
+        Some(message) if message.role == Role::Assistant => Ok(conversation),
+        Some(_) => Err(AnswerError::Invalid("last message is not from the agent")),
+        None => Err(AnswerError::Invalid("no messages")),
+    }
+}
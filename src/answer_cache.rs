@@ -0,0 +1,286 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::agent::{AgentEvent, CallContext, CogitoAgent};
+use crate::answer::{ANSWER_PROTOCOL_VERSION, is_supported_version};
+use crate::api_messages::{BAD_SESSION, GenericResponse};
+use crate::login::validate_session;
+use crate::proto::{Answer, AskOptions, Question};
+use crate::user::User;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse, Responder, get};
+use log::{error, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tonic::Status;
+use utoipa::ToSchema;
+
+// Many students in the same course open with literally the same question. Answers to those are
+// kept in Postgres for a while so only the first one costs an agent call.
+//
+// The cache is best effort: if the database misbehaves the question simply goes to the agent.
+
+/// Cache of agent answers to identical questions.
+pub struct AnswerCache {
+    /// How long answers are reused. The cache is disabled without a TTL.
+    ttl: Option<Duration>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// JSON response with cache metrics since the API started.
+#[derive(Serialize, ToSchema)]
+pub struct AnswerCacheStats {
+    enabled: bool,
+    hits: u64,
+    misses: u64,
+    /// Share of cacheable questions answered from the cache.
+    hit_ratio: f64,
+    /// Answers currently stored, including expired ones not yet cleaned up.
+    entries: i64,
+}
+
+/// Lowercase the question and collapse whitespace, so trivial differences still hit the cache.
+fn normalize(content: &str) -> String {
+    content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Key identifying a question, what came before it, the options it was asked with and who would
+/// answer it.
+fn cache_key(question: &Question, agent_version: &str) -> String {
+    let mut hasher = Sha256::new();
+
+    // Every part is terminated, so moving text between parts changes the key.
+    let mut part = |value: &str| {
+        hasher.update(value.as_bytes());
+        hasher.update([0]);
+    };
+
+    part(&ANSWER_PROTOCOL_VERSION.to_string());
+    part(agent_version);
+    part(&normalize(&question.content));
+
+    // A follow-up means something else after different turns.
+    part(&question.history.len().to_string());
+    for message in &question.history {
+        part(&message.role);
+        part(&message.content);
+    }
+    part(&question.summary);
+
+    let options = question.options.clone().unwrap_or_default();
+    let AskOptions {
+        model,
+        mut corpora,
+        mut tools,
+        parameters,
+    } = options;
+    corpora.sort();
+    tools.sort();
+    let mut parameters: Vec<_> = parameters.into_iter().collect();
+    parameters.sort_by(|a, b| a.0.cmp(&b.0));
+
+    part(&model);
+    part(&corpora.join(","));
+    part(&tools.join(","));
+    for (name, value) in parameters {
+        part(&format!("{}={}", name, value));
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+impl AnswerCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        AnswerCache {
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Ask the agent a question, reusing a stored answer to an identical question if there is one.
+    ///
+    /// Questions with documents are never cached since those are personal. Users who opted out
+    /// always get a fresh answer and their answers aren't stored, and so do conversations pinned to
+    /// a backend since they want that very deployment to answer. Answers the user approved or denied
+    /// actions for aren't stored either, as those decisions shaped them. Cached answers come
+    /// without any events but keep the provenance of the original answer.
+    pub(crate) async fn ask(
+        &self,
+        agent: &CogitoAgent,
+        db: &PgPool,
+        user: &User,
+        question: Question,
+        context: &CallContext,
+        mut on_event: impl FnMut(AgentEvent),
+    ) -> Result<Answer, Status> {
+        let Some(ttl) = self.ttl else {
+            return agent.ask_with_progress(question, context, on_event).await;
+        };
+        if user.answer_cache_opt_out || !question.documents.is_empty() || context.backend.is_some()
+        {
+            return agent.ask_with_progress(question, context, on_event).await;
+        }
+
+        // Answers from another agent version shouldn't be reused, so the version is part of the
+        // key. Agents that can't tell us their version aren't cached.
+        let agent_version = match agent.capabilities().await {
+            Ok(capabilities) => capabilities.agent_version,
+            Err(status) => {
+                warn!("Answer cache bypassed, agent version unknown: {}", status);
+                return agent.ask_with_progress(question, context, on_event).await;
+            }
+        };
+
+        let key = cache_key(&question, &agent_version);
+
+        match sqlx::query!(
+            r#"
+            update answer_cache set hits = hits + 1
+            where cache_key = $1 and expires_at > now()
+            returning content, protocol_version, agent_version, model
+            "#,
+            key
+        )
+        .fetch_optional(db)
+        .await
+        {
+            Ok(Some(cached)) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Answer {
+                    content: cached.content,
+                    protocol_version: cached.protocol_version as u32,
+                    agent_version: cached.agent_version,
+                    model: cached.model,
+                });
+            }
+            Ok(None) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                error!("Failed to read the answer cache: {}", e);
+                return agent.ask_with_progress(question, context, on_event).await;
+            }
+        }
+
+        let mut asked_approval = false;
+        let answer = agent
+            .ask_with_progress(question, context, |event| {
+                asked_approval |= matches!(event, AgentEvent::ApprovalRequested { .. });
+                on_event(event)
+            })
+            .await?;
+
+        if !asked_approval {
+            self.store(db, &key, &agent_version, &answer, ttl).await;
+        }
+
+        Ok(answer)
+    }
+
+    /// Store an answer, clearing out expired ones while at it.
+    async fn store(
+        &self,
+        db: &PgPool,
+        key: &str,
+        agent_version: &str,
+        answer: &Answer,
+        ttl: Duration,
+    ) {
+        // Answers the API can't use would only be served again.
+        if !is_supported_version(answer.protocol_version) {
+            return;
+        }
+
+        let result = sqlx::query!(
+            r#"
+            insert into answer_cache
+                (cache_key, agent_version, model, content, protocol_version, expires_at)
+            values ($1, $2, $3, $4, $5, now() + make_interval(secs => $6))
+            on conflict (cache_key) do update
+            set model = excluded.model,
+                content = excluded.content,
+                protocol_version = excluded.protocol_version,
+                created_at = now(),
+                expires_at = excluded.expires_at,
+                hits = 0
+            "#,
+            key,
+            agent_version,
+            answer.model,
+            answer.content,
+            answer.protocol_version as i32,
+            ttl.as_secs_f64()
+        )
+        .execute(db)
+        .await;
+
+        if let Err(e) = result {
+            error!("Failed to store an answer in the cache: {}", e);
+            return;
+        }
+
+        if let Err(e) = sqlx::query!("delete from answer_cache where expires_at <= now()")
+            .execute(db)
+            .await
+        {
+            error!("Failed to clean up the answer cache: {}", e);
+        }
+    }
+}
+
+/// Report how well the answer cache is doing.
+///
+/// Counting the entries scans the whole cache, so like `/agent/status` this requires a session.
+#[utoipa::path(
+    get,
+    path = "/agent/cache",
+    responses(
+        (status = 200, description = "Answer cache metrics retrieved successfully.", body = AnswerCacheStats),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+    )
+)]
+#[get("/agent/cache")]
+pub async fn answer_cache_stats(
+    req: HttpRequest,
+    cache: Data<AnswerCache>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    if let Err(e) = validate_session(&req, db.get_ref()).await {
+        return e;
+    }
+
+    let hits = cache.hits.load(Ordering::Relaxed);
+    let misses = cache.misses.load(Ordering::Relaxed);
+
+    let entries = match sqlx::query_scalar!(r#"select count(*) as "count!" from answer_cache"#)
+        .fetch_one(db.get_ref())
+        .await
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            // Still worth reporting the counters.
+            error!("Failed to count answer cache entries: {}", e);
+            0
+        }
+    };
+
+    let lookups = hits + misses;
+    HttpResponse::Ok().json(AnswerCacheStats {
+        enabled: cache.ttl.is_some(),
+        hits,
+        misses,
+        hit_ratio: if lookups == 0 {
+            0.0
+        } else {
+            hits as f64 / lookups as f64
+        },
+        entries,
+    })
+}
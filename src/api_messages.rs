@@ -29,6 +29,16 @@ pub static FORBIDDEN: &'static str = "You do not have access to this.";
 pub static AGENT_FAILED_TO_COMMUNICATE: &'static str =
     "Failed to communicate with the cogito agent.";
 
+/// The cogito agent refused the question, usually because of unsupported options.
+pub static AGENT_REJECTED_QUESTION: &str =
+    "The cogito agent rejected the question. Check the chosen options.";
+
+/// The conversation was pinned to an agent backend that isn't configured.
+pub static AGENT_UNKNOWN_BACKEND: &str = "Unknown cogito agent backend.";
+
+/// The cogito agent answered with something the API does not understand.
+pub static AGENT_INVALID_RESPONSE: &str = "The cogito agent returned an invalid response.";
+
 /// Generic error/info response returned by the API.
 #[derive(Serialize, ToSchema)]
 pub struct GenericResponse {
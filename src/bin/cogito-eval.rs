@@ -0,0 +1,167 @@
+//! Replays questions through the Cogito agent and compares runs, as a check before upgrading it.
+//!
+//! Usage:
+//! - `cogito-eval run <questions.json> <run.json>`: ask a JSON array of `BenchmarkQuestion`s and
+//!   write the answers, latencies and citation counts to a run file.
+//! - `cogito-eval replay <stored.json> <replayed.json> [limit]`: ask the questions of the latest
+//!   stored conversations again, writing both the stored and the new answers, and compare them.
+//! - `cogito-eval diff <baseline.json> <candidate.json>`: compare two run files.
+//!
+//! Reports are printed and the process exits with 1 if the candidate fails a question the baseline
+//! answered. The agent is configured with the same `COGITO_AGENT_*` environment variables as the
+//! API, replaying also needs `DATABASE_URL`. Documents attached to stored conversations aren't
+//! replayed.
+
+use std::env;
+use std::error::Error;
+use std::process::ExitCode;
+
+use chrono::Utc;
+use cogito_api::agent::{AgentConfig, CogitoAgent};
+use cogito_api::answer::AgentConversation;
+use cogito_api::eval::{BenchmarkQuestion, EvalRun, diff, run_benchmark, stored_record};
+use dotenvy::dotenv;
+use env_logger::Env;
+use log::{info, warn};
+use sqlx::PgPool;
+
+const USAGE: &str = "Usage:
+  cogito-eval run <questions.json> <run.json>
+  cogito-eval replay <stored.json> <replayed.json> [limit]
+  cogito-eval diff <baseline.json> <candidate.json>";
+
+/// How many stored conversations are replayed by default.
+const DEFAULT_REPLAY_LIMIT: i64 = 50;
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, Box<dyn Error>> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", path, e).into())
+}
+
+fn write_json<T: serde::Serialize>(path: &str, value: &T) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, serde_json::to_string_pretty(value)?)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(())
+}
+
+/// Print the comparison of two runs and tell whether the candidate regressed.
+fn report(baseline: &EvalRun, candidate: &EvalRun) -> bool {
+    let diff = diff(baseline, candidate);
+    println!("{}", diff);
+    diff.has_regressions()
+}
+
+/// Read the latest stored conversations as a run of how they were originally answered.
+async fn stored_run(db: &PgPool, limit: i64) -> Result<EvalRun, Box<dyn Error>> {
+    let rows = sqlx::query!(
+        r#"
+        select conversation_id, conversation from conversations
+        order by created_at desc limit $1
+        "#,
+        limit
+    )
+    .fetch_all(db)
+    .await?;
+
+    let records = rows
+        .into_iter()
+        .filter_map(|row| {
+            let id = format!("conversation-{}", row.conversation_id);
+            match serde_json::from_value::<AgentConversation>(row.conversation) {
+                Ok(conversation) => stored_record(id, &conversation),
+                Err(e) => {
+                    warn!("Skipping {}, it can't be read: {}", id, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    Ok(EvalRun {
+        started_at: Utc::now(),
+        records,
+    })
+}
+
+async fn run(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    match args {
+        [command, questions, output] if command == "run" => {
+            let questions: Vec<BenchmarkQuestion> = read_json(questions)?;
+            let agent = CogitoAgent::connect_lazy(AgentConfig::from_env()?)?;
+
+            info!("Asking {} benchmark questions.", questions.len());
+            let run = run_benchmark(&agent, &questions).await;
+            write_json(output, &run)?;
+
+            let failed = run
+                .records
+                .iter()
+                .filter(|record| record.error.is_some())
+                .count();
+            println!(
+                "{} answered, {} failed.",
+                run.records.len() - failed,
+                failed
+            );
+            Ok(false)
+        }
+        [command, stored, replayed, rest @ ..] if command == "replay" && rest.len() <= 1 => {
+            let limit = match rest.first() {
+                Some(limit) => limit.parse().map_err(|_| "The limit must be a number.")?,
+                None => DEFAULT_REPLAY_LIMIT,
+            };
+
+            let database_url =
+                env::var("DATABASE_URL").map_err(|_| "Expected `DATABASE_URL` to replay.")?;
+            let db = PgPool::connect(&database_url).await?;
+            let agent = CogitoAgent::connect_lazy(AgentConfig::from_env()?)?;
+
+            let baseline = stored_run(&db, limit).await?;
+            write_json(stored, &baseline)?;
+
+            // Stored answers are asked again with the model that originally answered.
+            let questions: Vec<BenchmarkQuestion> = baseline
+                .records
+                .iter()
+                .map(|record| BenchmarkQuestion {
+                    id: record.id.clone(),
+                    content: record.question.clone(),
+                    model: record.model.clone(),
+                })
+                .collect();
+
+            info!("Replaying {} stored conversations.", questions.len());
+            let candidate = run_benchmark(&agent, &questions).await;
+            write_json(replayed, &candidate)?;
+
+            Ok(report(&baseline, &candidate))
+        }
+        [command, baseline, candidate] if command == "diff" => {
+            let baseline: EvalRun = read_json(baseline)?;
+            let candidate: EvalRun = read_json(candidate)?;
+            Ok(report(&baseline, &candidate))
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
+    dotenv().ok();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match run(&args).await {
+        Ok(false) => ExitCode::SUCCESS,
+        Ok(true) => {
+            eprintln!("The candidate fails questions the baseline answered.");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
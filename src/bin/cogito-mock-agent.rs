@@ -0,0 +1,32 @@
+//! Runs the mock Cogito agent so the API can be used without the real one.
+//!
+//! Configured through the environment (or a `.env` file):
+//! - `COGITO_MOCK_AGENT_URL`: address to listen on, defaults to "127.0.0.1:9999".
+//! - `COGITO_MOCK_AGENT_CONFIG`: optional path to a JSON `MockConfig` with scripted replies,
+//!   citations, latency and error rate.
+
+use std::env;
+use std::error::Error;
+
+use cogito_api::mock_agent::{MockAgent, MockConfig};
+use dotenvy::dotenv;
+use env_logger::Env;
+use log::info;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
+    dotenv().ok();
+
+    let address = env::var("COGITO_MOCK_AGENT_URL").unwrap_or("127.0.0.1:9999".to_string());
+
+    let config: MockConfig = match env::var("COGITO_MOCK_AGENT_CONFIG") {
+        Ok(path) => serde_json::from_str(&std::fs::read_to_string(&path)?)?,
+        Err(_) => MockConfig::default(),
+    };
+
+    info!("Mock Cogito agent listening on {}", address);
+    MockAgent::new(config).serve(address.parse()?).await?;
+
+    Ok(())
+}
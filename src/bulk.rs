@@ -0,0 +1,264 @@
+use crate::api_messages::{BAD_SESSION, GenericResponse, SERVER_ERROR};
+use crate::compare::blind_unvoted;
+use crate::conversation::{Conversation, fetch_conversation};
+use crate::login::validate_session;
+use crate::revision::{RevisionKind, record_revision};
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Json};
+use actix_web::{HttpRequest, HttpResponse, Responder, post};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use utoipa::ToSchema;
+
+/// The most conversations a single bulk request may touch.
+const MAX_BULK_CONVERSATIONS: usize = 1000;
+
+/// Operation applied to every conversation in a bulk request.
+#[derive(Deserialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    /// Permanently delete the conversations.
+    Delete,
+    /// Archive the conversations.
+    Archive,
+    /// Take the conversations out of the archive.
+    Unarchive,
+    /// File the conversations under `folder`, or remove them from any folder if it is left out.
+    MoveToFolder,
+    /// Add `tag` to the conversations.
+    Tag,
+    /// Return the full conversations in the response.
+    Export,
+}
+
+/// Post request data to operate on many conversations at once.
+#[derive(Deserialize, ToSchema)]
+pub struct BulkRequest {
+    action: BulkAction,
+    conversation_ids: Vec<i32>,
+    /// Destination folder for `move_to_folder`.
+    folder: Option<String>,
+    /// Tag to add for `tag`.
+    tag: Option<String>,
+}
+
+/// Outcome of a bulk action on a single conversation.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkStatus {
+    Ok,
+    NotFound,
+    Forbidden,
+}
+
+/// Per conversation result of a bulk request.
+#[derive(Serialize, ToSchema)]
+pub struct BulkResult {
+    conversation_id: i32,
+    status: BulkStatus,
+    /// The conversation itself, only present for `export`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversation: Option<Conversation>,
+}
+
+/// JSON response of a bulk request.
+#[derive(Serialize, ToSchema)]
+pub struct BulkResponse {
+    results: Vec<BulkResult>,
+}
+
+/// Apply a bulk action to a single conversation that is already known to belong to the user.
+///
+/// Every conversation that actually changed gets a revision, deleted ones right before.
+async fn apply_action(
+    conn: &mut PgConnection,
+    info: &BulkRequest,
+    conversation: &Conversation,
+) -> Result<(), sqlx::Error> {
+    let conversation_id = conversation.conversation_id;
+
+    let (result, kind) = match info.action {
+        BulkAction::Delete => {
+            record_revision(&mut *conn, conversation_id, RevisionKind::Deleted).await?;
+            sqlx::query!(
+                "delete from conversations where conversation_id = $1",
+                conversation_id
+            )
+            .execute(&mut *conn)
+            .await?;
+            return Ok(());
+        }
+        BulkAction::Archive => (
+            sqlx::query!(
+                "update conversations set archived = true where conversation_id = $1 and not archived",
+                conversation_id
+            )
+            .execute(&mut *conn)
+            .await?,
+            RevisionKind::Archived,
+        ),
+        BulkAction::Unarchive => (
+            sqlx::query!(
+                "update conversations set archived = false where conversation_id = $1 and archived",
+                conversation_id
+            )
+            .execute(&mut *conn)
+            .await?,
+            RevisionKind::Unarchived,
+        ),
+        BulkAction::MoveToFolder => (
+            sqlx::query!(
+                r#"
+                update conversations set folder = $1
+                where conversation_id = $2 and folder is distinct from $1
+                "#,
+                info.folder,
+                conversation_id
+            )
+            .execute(&mut *conn)
+            .await?,
+            RevisionKind::Moved,
+        ),
+        BulkAction::Tag => (
+            sqlx::query!(
+                r#"
+                update conversations set tags = array_append(tags, $1)
+                where conversation_id = $2 and not ($1 = any(tags))
+                "#,
+                info.tag,
+                conversation_id
+            )
+            .execute(&mut *conn)
+            .await?,
+            RevisionKind::Tagged,
+        ),
+        // Nothing to change, the conversation is returned as is.
+        BulkAction::Export => return Ok(()),
+    };
+
+    if result.rows_affected() > 0 {
+        record_revision(conn, conversation_id, kind).await?;
+    }
+
+    Ok(())
+}
+
+/// Delete, archive, unarchive, move, tag, or export many conversations in one transaction.
+///
+/// Every conversation goes through the same ownership checks as a single conversation request.
+/// Conversations that can't be accessed are reported per ID and don't stop the others from being
+/// processed. Any database error rolls back the whole request.
+#[utoipa::path(
+    post,
+    path = "/conversations/bulk",
+    request_body = BulkRequest,
+    responses(
+        (status = 200, description = "Bulk action applied, see the per conversation results.", body = BulkResponse),
+        (status = 400, description = "Invalid bulk request.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[post("/conversations/bulk")]
+pub async fn bulk_conversations(
+    req: HttpRequest,
+    info: Json<BulkRequest>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let mut info = info.into_inner();
+
+    if info.conversation_ids.len() > MAX_BULK_CONVERSATIONS {
+        return HttpResponse::BadRequest().json(GenericResponse {
+            message: "Too many conversations in one bulk request.",
+        });
+    }
+
+    if matches!(info.action, BulkAction::Tag) && info.tag.is_none() {
+        return HttpResponse::BadRequest().json(GenericResponse {
+            message: "A tag is required to tag conversations.",
+        });
+    }
+
+    // Handle each conversation once, keeping the order they were given in.
+    let mut seen = std::collections::HashSet::new();
+    info.conversation_ids.retain(|id| seen.insert(*id));
+
+    let mut tx = match db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to begin bulk transaction: {}", e);
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    };
+
+    let mut results = Vec::with_capacity(info.conversation_ids.len());
+    for &conversation_id in &info.conversation_ids {
+        let mut conversation = match fetch_conversation(conversation_id, &user, &mut *tx).await {
+            Ok(convo) => convo,
+            Err(e) => {
+                let status = match e.status() {
+                    StatusCode::NOT_FOUND => BulkStatus::NotFound,
+                    StatusCode::FORBIDDEN => BulkStatus::Forbidden,
+                    // Database error, the transaction is unusable now.
+                    _ => return e,
+                };
+
+                results.push(BulkResult {
+                    conversation_id,
+                    status,
+                    conversation: None,
+                });
+                continue;
+            }
+        };
+
+        if let Err(e) = apply_action(&mut tx, &info, &conversation).await {
+            error!(
+                "Failed bulk action on conversation {} for user {}: {}",
+                conversation_id, user.user_name, e
+            );
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+
+        // Exports are as blind as fetching the conversation on its own.
+        if matches!(info.action, BulkAction::Export)
+            && let Err(e) = blind_unvoted(&mut conversation, &mut *tx).await
+        {
+            error!(
+                "Failed to check comparisons of conversation {} for user {}: {}",
+                conversation_id, user.user_name, e
+            );
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+
+        results.push(BulkResult {
+            conversation_id,
+            status: BulkStatus::Ok,
+            conversation: matches!(info.action, BulkAction::Export).then_some(conversation),
+        });
+    }
+
+    if let Err(e) = tx.commit().await {
+        error!(
+            "Failed to commit bulk action for user {}: {}",
+            user.user_name, e
+        );
+        return HttpResponse::InternalServerError().json(GenericResponse {
+            message: SERVER_ERROR,
+        });
+    }
+
+    HttpResponse::Ok().json(BulkResponse { results })
+}
@@ -0,0 +1,417 @@
+use crate::agent_info::AgentOptions;
+use crate::api_messages::{
+    AGENT_FAILED_TO_COMMUNICATE, AGENT_INVALID_RESPONSE, AGENT_REJECTED_QUESTION,
+    AGENT_UNKNOWN_BACKEND, BAD_SESSION, GenericResponse, SERVER_ERROR,
+};
+use crate::conversation::{
+    AgentServices, Conversation, CreateConversationRequest, answer_conversation, store_error,
+};
+use crate::login::validate_session;
+use crate::request_id::RequestId;
+use crate::user::User;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder, get, post};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgExecutor, PgPool};
+use utoipa::ToSchema;
+
+// To evaluate agent changes with real users, the same question is answered by two variants (agent
+// backends and/or options) and the user votes for the better answer. Which variant ends up as "a"
+// is random and the variants are only revealed after voting, so the vote is blind.
+//
+// Both answers are stored as regular conversations, linked together by a comparison. Until the
+// vote, those conversations are returned without their backend and provenance, which would tell the
+// variants apart.
+
+/// The message returned when a comparison doesn't exist or belongs to someone else.
+static COMPARISON_NOT_FOUND: &str = "Comparison not found.";
+
+/// The message returned when voting on a comparison twice.
+static ALREADY_VOTED: &str = "This comparison was already voted on.";
+
+/// One side of a comparison.
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default)]
+pub struct CompareVariant {
+    /// URL of an agent backend from `/agent/status`, balanced between all of them if missing.
+    agent_backend: Option<String>,
+    options: AgentOptions,
+}
+
+/// Post request data to compare two agent variants.
+#[derive(Deserialize, ToSchema)]
+pub struct CompareRequest {
+    question: String,
+    first: CompareVariant,
+    second: CompareVariant,
+}
+
+/// JSON response after answering a question with both variants.
+#[derive(Serialize, ToSchema)]
+pub struct CompareResponse {
+    comparison_id: i32,
+    conversation_a: i32,
+    conversation_b: i32,
+}
+
+/// Which answer the user preferred.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+    A,
+    B,
+    Tie,
+}
+
+impl Vote {
+    fn as_str(self) -> &'static str {
+        match self {
+            Vote::A => "a",
+            Vote::B => "b",
+            Vote::Tie => "tie",
+        }
+    }
+}
+
+/// Post request data to vote on a comparison.
+#[derive(Deserialize, ToSchema)]
+pub struct VoteRequest {
+    vote: Vote,
+}
+
+/// A comparison of two answers to the same question.
+#[derive(Serialize, ToSchema)]
+pub struct Comparison {
+    comparison_id: i32,
+    question: String,
+    conversation_a: i32,
+    conversation_b: i32,
+    /// Either "a", "b" or "tie", missing until the user voted.
+    vote: Option<String>,
+    /// What answered as "a", only revealed after voting.
+    variant_a: Option<serde_json::Value>,
+    /// What answered as "b", only revealed after voting.
+    variant_b: Option<serde_json::Value>,
+    #[schema(value_type = String, format = "date-time")]
+    created_at: DateTime<Utc>,
+}
+
+/// Whether a conversation answers one side of a comparison that wasn't voted on yet.
+pub(crate) async fn is_unvoted_variant(
+    conversation_id: i32,
+    db: impl PgExecutor<'_>,
+) -> Result<bool, Error> {
+    sqlx::query_scalar!(
+        r#"
+        select exists(
+            select 1 from comparisons
+            where vote is null and (conversation_a = $1 or conversation_b = $1)
+        ) as "exists!"
+        "#,
+        conversation_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Remove the provenance of every message from stored conversation contents.
+pub(crate) fn hide_provenance(conversation: &mut serde_json::Value) {
+    if let Some(messages) = conversation
+        .get_mut("messages")
+        .and_then(|messages| messages.as_array_mut())
+    {
+        for message in messages
+            .iter_mut()
+            .filter_map(|message| message.as_object_mut())
+        {
+            message.remove("provenance");
+        }
+    }
+}
+
+/// Hide which variant answered a conversation, if it belongs to a comparison that wasn't voted on.
+pub(crate) async fn blind_unvoted(
+    conversation: &mut Conversation,
+    db: impl PgExecutor<'_>,
+) -> Result<(), Error> {
+    if is_unvoted_variant(conversation.conversation_id, db).await? {
+        conversation.agent_backend = None;
+        hide_provenance(&mut conversation.conversation);
+    }
+
+    Ok(())
+}
+
+/// Fetch a comparison of the user, hiding the variants until it was voted on.
+async fn fetch_comparison(
+    comparison_id: i32,
+    user: &User,
+    db: &PgPool,
+) -> Result<Comparison, HttpResponse> {
+    match sqlx::query_as!(
+        Comparison,
+        r#"
+        select comparison_id, question, conversation_a, conversation_b, vote,
+               case when vote is null then null else variant_a end as "variant_a?",
+               case when vote is null then null else variant_b end as "variant_b?",
+               created_at
+        from comparisons
+        where comparison_id = $1 and user_id = $2
+        "#,
+        comparison_id,
+        user.user_id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(comparison) => Ok(comparison),
+        Err(Error::RowNotFound) => Err(HttpResponse::NotFound().json(GenericResponse {
+            message: COMPARISON_NOT_FOUND,
+        })),
+        Err(e) => {
+            error!(
+                "Failed to retrieve comparison {} for user {}: {}",
+                comparison_id, user.user_name, e
+            );
+            Err(HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            }))
+        }
+    }
+}
+
+/// Ask the same question with two agent variants and store both answers for a blind vote.
+///
+/// Both variants are asked in parallel. If either fails, neither answer is kept.
+#[utoipa::path(
+    post,
+    path = "/compare",
+    request_body = CompareRequest,
+    responses(
+        (status = 200, description = "Both answers stored successfully.", body = CompareResponse),
+        (status = 400, description = AGENT_REJECTED_QUESTION, body = GenericResponse),
+        (status = 400, description = AGENT_UNKNOWN_BACKEND, body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 502, description = AGENT_INVALID_RESPONSE, body = GenericResponse),
+        (status = 503, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+    )
+)]
+#[post("/compare")]
+pub async fn create_comparison(
+    req: HttpRequest,
+    info: Json<CompareRequest>,
+    db: Data<PgPool>,
+    services: AgentServices,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let CompareRequest {
+        question,
+        first,
+        second,
+    } = info.into_inner();
+
+    // Shuffle the variants so their order doesn't give them away.
+    let (variant_a, variant_b) = if rand::random() {
+        (first, second)
+    } else {
+        (second, first)
+    };
+
+    let (variant_a_json, variant_b_json) = match (
+        serde_json::to_value(&variant_a),
+        serde_json::to_value(&variant_b),
+    ) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => {
+            // Serializing these can't really fail.
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    };
+
+    let request = |variant: CompareVariant| CreateConversationRequest {
+        initial_message: question.clone(),
+        document_ids: Vec::new(),
+        options: variant.options,
+        progress_id: None,
+        agent_backend: variant.agent_backend,
+    };
+
+    let request_id = RequestId::of(&req);
+    let (answer_a, answer_b) = tokio::join!(
+        answer_conversation(
+            &user,
+            request(variant_a),
+            request_id.clone(),
+            db.get_ref(),
+            &services,
+        ),
+        answer_conversation(
+            &user,
+            request(variant_b),
+            request_id,
+            db.get_ref(),
+            &services,
+        ),
+    );
+
+    // Half a comparison can't be voted on.
+    let (answer_a, answer_b) = match (answer_a, answer_b) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+
+    let stored: Result<(i32, i32, i32), Error> = async {
+        let mut tx = db.begin().await?;
+
+        let conversation_a = answer_a.store(&mut tx, &user).await?;
+        let conversation_b = answer_b.store(&mut tx, &user).await?;
+        let comparison_id = sqlx::query_scalar!(
+            r#"
+            insert into comparisons (
+                user_id, question, conversation_a, conversation_b, variant_a, variant_b
+            )
+            values ($1, $2, $3, $4, $5, $6) returning comparison_id
+            "#,
+            user.user_id,
+            question,
+            conversation_a,
+            conversation_b,
+            variant_a_json,
+            variant_b_json
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok((comparison_id, conversation_a, conversation_b))
+    }
+    .await;
+
+    let (comparison_id, conversation_a, conversation_b) = match stored {
+        Ok(stored) => stored,
+        Err(e) => return store_error(e, &user),
+    };
+
+    answer_a.done(conversation_a);
+    answer_b.done(conversation_b);
+
+    HttpResponse::Ok().json(CompareResponse {
+        comparison_id,
+        conversation_a,
+        conversation_b,
+    })
+}
+
+/// Get a comparison by its ID.
+///
+/// The variants are only included once the comparison was voted on.
+#[utoipa::path(
+    get,
+    path = "/compare/{comparison_id}",
+    params(
+        ("comparison_id" = i32, Path, description = "The ID of the comparison to retrieve.")
+    ),
+    responses(
+        (status = 200, description = "Comparison retrieved successfully.", body = Comparison),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = COMPARISON_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[get("/compare/{comparison_id}")]
+pub async fn get_comparison(
+    comparison_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match fetch_comparison(*comparison_id, &user, db.get_ref()).await {
+        Ok(comparison) => HttpResponse::Ok().json(comparison),
+        Err(e) => e,
+    }
+}
+
+/// Vote for the better answer of a comparison, revealing the variants.
+///
+/// Every comparison can only be voted on once, since the vote is no longer blind afterwards.
+#[utoipa::path(
+    post,
+    path = "/compare/{comparison_id}/vote",
+    params(
+        ("comparison_id" = i32, Path, description = "The ID of the comparison to vote on.")
+    ),
+    request_body = VoteRequest,
+    responses(
+        (status = 200, description = "Vote recorded successfully.", body = Comparison),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = COMPARISON_NOT_FOUND, body = GenericResponse),
+        (status = 409, description = ALREADY_VOTED, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[post("/compare/{comparison_id}/vote")]
+pub async fn vote_comparison(
+    comparison_id: Path<i32>,
+    req: HttpRequest,
+    info: Json<VoteRequest>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let comparison = match fetch_comparison(*comparison_id, &user, db.get_ref()).await {
+        Ok(comparison) => comparison,
+        Err(e) => return e,
+    };
+
+    let vote = info.into_inner().vote;
+
+    // Checking the vote again guards against two votes racing each other.
+    match sqlx::query!(
+        r#"
+        update comparisons set vote = $1, voted_at = now()
+        where comparison_id = $2 and vote is null
+        "#,
+        vote.as_str(),
+        comparison.comparison_id
+    )
+    .execute(db.get_ref())
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            return HttpResponse::Conflict().json(GenericResponse {
+                message: ALREADY_VOTED,
+            });
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!(
+                "Failed to record vote on comparison {} for user {}: {}",
+                comparison.comparison_id, user.user_name, e
+            );
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    }
+
+    match fetch_comparison(comparison.comparison_id, &user, db.get_ref()).await {
+        Ok(comparison) => HttpResponse::Ok().json(comparison),
+        Err(e) => e,
+    }
+}
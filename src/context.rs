@@ -0,0 +1,290 @@
+//! Fitting the history of a conversation into the agent's context window.
+//!
+//! Every follow-up question is sent with the turns before it, and long research conversations soon
+//! exceed what the agent's model can take. The history is cut down to a token budget using one of a
+//! few strategies. Tokens are only estimated since the API doesn't know the agent's tokenizer, so
+//! the budget should leave some headroom.
+
+use std::str::FromStr;
+
+use crate::agent::CogitoAgent;
+use crate::answer::{AgentMessage, Role};
+use crate::proto::{HistoryMessage, SummaryRequest};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Characters per token of English prose for common tokenizers, a deliberately low guess.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens spent on the role and separators of every message.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// How history that doesn't fit is cut down.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextStrategy {
+    /// Send only the newest messages that fit.
+    SlidingWindow,
+    /// Always send the first message, which usually frames the research, then the newest ones.
+    PinnedFirst,
+    /// Have the agent summarize older messages and send the summary instead of them.
+    Summarize,
+}
+
+impl FromStr for ContextStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sliding_window" => Ok(ContextStrategy::SlidingWindow),
+            "pinned_first" => Ok(ContextStrategy::PinnedFirst),
+            "summarize" => Ok(ContextStrategy::Summarize),
+            other => Err(format!("Unknown context strategy \"{}\".", other)),
+        }
+    }
+}
+
+/// Settings for fitting history into the context window.
+pub struct ContextConfig {
+    /// Estimated tokens of history and summary sent with a question.
+    pub max_tokens: usize,
+    pub strategy: ContextStrategy,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        ContextConfig {
+            max_tokens: 16_000,
+            strategy: ContextStrategy::PinnedFirst,
+        }
+    }
+}
+
+impl ContextConfig {
+    /// Read the configuration from `COGITO_CONTEXT_*` environment variables, falling back to the
+    /// defaults for anything not set.
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = ContextConfig::default();
+
+        Ok(ContextConfig {
+            max_tokens: match std::env::var("COGITO_CONTEXT_MAX_TOKENS") {
+                Ok(max_tokens) => max_tokens.parse().map_err(|_| {
+                    "Invalid `COGITO_CONTEXT_MAX_TOKENS` environment variable.".to_string()
+                })?,
+                Err(_) => defaults.max_tokens,
+            },
+            strategy: match std::env::var("COGITO_CONTEXT_STRATEGY") {
+                Ok(strategy) => strategy.parse()?,
+                Err(_) => defaults.strategy,
+            },
+        })
+    }
+}
+
+/// Summary of the first messages of a conversation, stored so it isn't redone for every question.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct ConversationSummary {
+    pub content: String,
+    /// How many messages from the start of the conversation it replaces.
+    pub covers: usize,
+}
+
+/// How the history was cut down for a question, recorded with the answer.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct AppliedContext {
+    /// The strategy used, missing if the whole history fit.
+    pub strategy: Option<ContextStrategy>,
+    /// Estimated tokens of the history and summary that were sent.
+    pub estimated_tokens: usize,
+    /// Messages that were left out entirely.
+    pub omitted_messages: usize,
+    /// Messages that were sent as part of the summary.
+    pub summarized_messages: usize,
+}
+
+/// History ready to be sent with a question.
+pub struct PreparedContext {
+    pub history: Vec<HistoryMessage>,
+    /// Goes into `Question.summary`, empty if there is none.
+    pub summary: String,
+    pub applied: AppliedContext,
+    /// A summary made while preparing, which should be stored with the conversation.
+    pub new_summary: Option<ConversationSummary>,
+}
+
+/// Estimate how many tokens a text takes up.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+fn message_tokens(message: &AgentMessage) -> usize {
+    estimate_tokens(&message.content) + MESSAGE_OVERHEAD
+}
+
+fn to_history(messages: &[AgentMessage]) -> Vec<HistoryMessage> {
+    messages
+        .iter()
+        .map(|message| HistoryMessage {
+            role: match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            }
+            .to_string(),
+            content: message.content.clone(),
+        })
+        .collect()
+}
+
+/// Find where the newest messages after `from` that fit into `budget` start.
+///
+/// The newest message is always kept, it is what the question follows up on.
+fn window_start(messages: &[AgentMessage], from: usize, budget: usize) -> usize {
+    let mut used = 0;
+    let mut start = messages.len();
+
+    while start > from {
+        let tokens = message_tokens(&messages[start - 1]);
+        if used + tokens > budget && start < messages.len() {
+            break;
+        }
+        used += tokens;
+        start -= 1;
+    }
+
+    start
+}
+
+impl PreparedContext {
+    /// Send `summary` followed by the messages from `start` on.
+    fn new(
+        messages: &[AgentMessage],
+        start: usize,
+        strategy: Option<ContextStrategy>,
+        summary: Option<&ConversationSummary>,
+    ) -> Self {
+        let summary_content = summary.map(|summary| summary.content.clone());
+        let summarized_messages = summary.map_or(0, |summary| summary.covers);
+
+        PreparedContext {
+            history: to_history(&messages[start..]),
+            applied: AppliedContext {
+                strategy,
+                estimated_tokens: messages[start..].iter().map(message_tokens).sum::<usize>()
+                    + summary_content.as_deref().map_or(0, estimate_tokens),
+                omitted_messages: start - summarized_messages,
+                summarized_messages,
+            },
+            summary: summary_content.unwrap_or_default(),
+            new_summary: None,
+        }
+    }
+}
+
+/// Cut the history of a conversation down to fit the context window.
+///
+/// `messages` is every message before the new question and `summary` the summary stored with the
+/// conversation, if any. With the `Summarize` strategy the agent is asked for a new summary when
+/// the stored one doesn't cover enough, falling back to a sliding window if that fails.
+pub async fn prepare_context(
+    agent: &CogitoAgent,
+    config: &ContextConfig,
+    messages: &[AgentMessage],
+    summary: Option<&ConversationSummary>,
+) -> PreparedContext {
+    let budget = config.max_tokens;
+    let total: usize = messages.iter().map(message_tokens).sum();
+
+    if total <= budget {
+        return PreparedContext::new(messages, 0, None, None);
+    }
+
+    match config.strategy {
+        ContextStrategy::SlidingWindow => sliding_window(messages, budget),
+        ContextStrategy::PinnedFirst => {
+            let first = message_tokens(&messages[0]);
+            let start = window_start(messages, 1, budget.saturating_sub(first));
+
+            let mut prepared =
+                PreparedContext::new(messages, start, Some(ContextStrategy::PinnedFirst), None);
+            prepared
+                .history
+                .insert(0, to_history(&messages[..1]).remove(0));
+            prepared.applied.estimated_tokens += first;
+            prepared.applied.omitted_messages -= 1;
+            prepared
+        }
+        ContextStrategy::Summarize => summarize(agent, messages, summary, budget).await,
+    }
+}
+
+fn sliding_window(messages: &[AgentMessage], budget: usize) -> PreparedContext {
+    let start = window_start(messages, 0, budget);
+    PreparedContext::new(messages, start, Some(ContextStrategy::SlidingWindow), None)
+}
+
+async fn summarize(
+    agent: &CogitoAgent,
+    messages: &[AgentMessage],
+    summary: Option<&ConversationSummary>,
+    budget: usize,
+) -> PreparedContext {
+    // A summary covering more than there is belongs to history that was since changed.
+    let summary = summary.filter(|summary| summary.covers <= messages.len());
+    let covered = summary.map_or(0, |summary| summary.covers);
+
+    if let Some(summary) = summary {
+        let rest: usize = messages[covered..].iter().map(message_tokens).sum();
+        if estimate_tokens(&summary.content) + rest <= budget {
+            return PreparedContext::new(
+                messages,
+                covered,
+                Some(ContextStrategy::Summarize),
+                Some(summary),
+            );
+        }
+    }
+
+    // Keep half the budget for recent messages, which matter most, and summarize the rest.
+    let start = window_start(messages, covered, budget / 2);
+    if start == covered {
+        return sliding_window(messages, budget);
+    }
+
+    let request = SummaryRequest {
+        previous_summary: summary
+            .map(|summary| summary.content.clone())
+            .unwrap_or_default(),
+        messages: to_history(&messages[covered..start]),
+        max_tokens: (budget / 2) as u32,
+    };
+
+    let content = match agent.summarize(request).await {
+        Ok(content) => content,
+        Err(status) => {
+            warn!(
+                "Failed to summarize conversation history, using a sliding window: {}",
+                status
+            );
+            return sliding_window(messages, budget);
+        }
+    };
+
+    let new_summary = ConversationSummary {
+        content,
+        covers: start,
+    };
+
+    // The agent may not have kept to the length asked for.
+    let remaining = budget.saturating_sub(estimate_tokens(&new_summary.content));
+    let kept = window_start(messages, start, remaining);
+
+    let mut prepared = PreparedContext::new(
+        messages,
+        kept,
+        Some(ContextStrategy::Summarize),
+        Some(&new_summary),
+    );
+    prepared.new_summary = Some(new_summary);
+    prepared
+}
@@ -1,19 +1,35 @@
-use crate::agent::CogitoAgent;
+use crate::action::ActionRegistry;
+use crate::agent::{AgentEvent, CallContext, CogitoAgent};
+use crate::agent_info::AgentOptions;
+use crate::answer::{AgentConversation, Provenance, parse_answer};
+use crate::answer_cache::AnswerCache;
 use crate::api_messages::{
-    AGENT_FAILED_TO_COMMUNICATE, BAD_SESSION, FORBIDDEN, GenericResponse, SERVER_ERROR,
+    AGENT_FAILED_TO_COMMUNICATE, AGENT_INVALID_RESPONSE, AGENT_REJECTED_QUESTION,
+    AGENT_UNKNOWN_BACKEND, BAD_SESSION, FORBIDDEN, GenericResponse, SERVER_ERROR,
 };
+use crate::compare::blind_unvoted;
+use crate::context::{ContextConfig, ConversationSummary, prepare_context};
+use crate::document::{DOCUMENT_NOT_FOUND, attach_documents, attachable_documents};
 use crate::login::validate_session;
+use crate::progress::{ProgressHub, ProgressReporter};
 use crate::proto::{Answer, Question};
+use crate::request_id::RequestId;
+use crate::revision::{RevisionKind, record_revision};
 use crate::user::User;
+use actix_web::dev::Payload;
+use actix_web::error::ErrorInternalServerError;
 use actix_web::web::Path;
 use actix_web::web::{Data, Form, Json};
-use actix_web::{Either, HttpRequest, HttpResponse, Responder, delete, get, post};
+use actix_web::{
+    Either, FromRequest, HttpRequest, HttpResponse, Responder, delete, get, post, put,
+};
 use chrono::{DateTime, Utc};
 use log::error;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sqlx::{Error, PgPool};
+use sqlx::{Error, PgConnection, PgExecutor, PgPool};
+use std::future::{Ready, ready};
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 /// Representation of a conversation with Cogito.
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -24,20 +40,332 @@ pub struct Conversation {
     pub(crate) conversation_title: String,
     #[schema(value_type = String, format = "date-time")]
     pub(crate) created_at: DateTime<Utc>,
+    /// Archived conversations are kept but hidden from the user's main list.
+    pub(crate) archived: bool,
+    /// Folder the user has filed this conversation under, if any.
+    pub(crate) folder: Option<String>,
+    pub(crate) tags: Vec<String>,
+    /// Identifies the conversation to the Cogito agent.
+    ///
+    /// Never sent to clients, anyone knowing it could link the agent's records back to the user.
+    #[serde(skip_serializing)]
+    pub(crate) pseudonym: Uuid,
+    /// URL of the agent backend the conversation is pinned to, if any.
+    ///
+    /// Kept internal like the pseudonym, backend URLs are only listed by `/agent/status`.
+    #[serde(skip_serializing)]
+    pub(crate) agent_backend: Option<String>,
+    /// Summary of the earliest turns sent to the agent instead of them, see `ConversationSummary`.
+    #[serde(skip_serializing)]
+    pub(crate) context_summary: Option<serde_json::Value>,
 }
 
 /// Post request data to create a new conversation with Cogito.
 #[derive(Deserialize, ToSchema)]
 pub struct CreateConversationRequest {
     /// The initial message to begin the conversation with Cogito.
-    initial_message: String,
+    pub(crate) initial_message: String,
+    /// Uploaded documents to give Cogito as context.
+    #[serde(default)]
+    pub(crate) document_ids: Vec<i32>,
+    /// How Cogito should answer.
+    #[serde(default)]
+    pub(crate) options: AgentOptions,
+    /// Client chosen UUID to follow the research progress at `/progress/{progress_id}`.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub(crate) progress_id: Option<Uuid>,
+    /// URL of an agent backend from `/agent/status` to pin the conversation to, so it is always
+    /// answered by the same deployment.
+    #[serde(default)]
+    pub(crate) agent_backend: Option<String>,
+}
+
+/// Post request data to ask a follow-up question in a conversation.
+#[derive(Deserialize, ToSchema)]
+pub struct FollowUpRequest {
+    /// The follow-up question.
+    content: String,
+    /// How Cogito should answer.
+    #[serde(default)]
+    options: AgentOptions,
+    /// Client chosen UUID to follow the research progress at `/progress/{progress_id}`.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    progress_id: Option<Uuid>,
+}
+
+/// Put request data to rename a conversation.
+#[derive(Deserialize, ToSchema)]
+pub struct RenameConversationRequest {
+    /// The new title of the conversation.
+    conversation_title: String,
 }
 
 /// JSON response after creating a new conversation.
 #[derive(Serialize, ToSchema)]
 pub struct CreateConversationResponse {
     /// Conversation identifier.
-    conversation_id: i32,
+    pub(crate) conversation_id: i32,
+}
+
+/// The agent and everything wrapped around calls to it, taken from the app data.
+pub(crate) struct AgentServices {
+    pub(crate) agent: Data<CogitoAgent>,
+    pub(crate) answer_cache: Data<AnswerCache>,
+    pub(crate) progress_hub: Data<ProgressHub>,
+    pub(crate) action_registry: Data<ActionRegistry>,
+    pub(crate) context_config: Data<ContextConfig>,
+}
+
+impl FromRequest for AgentServices {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let services = (|| {
+            Some(AgentServices {
+                agent: req.app_data::<Data<CogitoAgent>>()?.clone(),
+                answer_cache: req.app_data::<Data<AnswerCache>>()?.clone(),
+                progress_hub: req.app_data::<Data<ProgressHub>>()?.clone(),
+                action_registry: req.app_data::<Data<ActionRegistry>>()?.clone(),
+                context_config: req.app_data::<Data<ContextConfig>>()?.clone(),
+            })
+        })();
+
+        ready(services.ok_or_else(|| {
+            error!("Agent services are missing from the app data.");
+            ErrorInternalServerError(SERVER_ERROR)
+        }))
+    }
+}
+
+/// A new conversation answered by Cogito, but not stored yet.
+pub(crate) struct AnsweredConversation {
+    conversation: AgentConversation,
+    pseudonym: Uuid,
+    agent_backend: Option<String>,
+    document_ids: Vec<i32>,
+    progress: ProgressReporter,
+}
+
+impl AnsweredConversation {
+    /// Store the conversation with its documents and first revision, returning its ID.
+    pub(crate) async fn store(&self, conn: &mut PgConnection, user: &User) -> Result<i32, Error> {
+        let conversation_id = sqlx::query_scalar!(
+            r#"
+            insert into conversations (user_id, conversation, pseudonym, agent_backend)
+            values ($1, $2, $3, $4) returning conversation_id
+            "#,
+            user.user_id,
+            sqlx::types::Json(&self.conversation) as _,
+            self.pseudonym,
+            self.agent_backend
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        attach_documents(&mut *conn, &self.document_ids, conversation_id, user).await?;
+        record_revision(conn, conversation_id, RevisionKind::Created).await?;
+
+        Ok(conversation_id)
+    }
+
+    /// Tell anyone following the progress where the stored conversation is.
+    pub(crate) fn done(self, conversation_id: i32) {
+        self.progress.done(conversation_id);
+    }
+}
+
+/// The response when storing an answered conversation failed.
+pub(crate) fn store_error(e: Error, user: &User) -> HttpResponse {
+    // A document was given to another conversation at the same time.
+    if let Error::RowNotFound = e {
+        return HttpResponse::NotFound().json(GenericResponse {
+            message: DOCUMENT_NOT_FOUND,
+        });
+    }
+
+    // This really shouldn't fail, but handle the error just in case.
+    error!(
+        "Failed to create new conversation for user {}: {}",
+        user.user_name, e
+    );
+    HttpResponse::InternalServerError().json(GenericResponse {
+        message: SERVER_ERROR,
+    })
+}
+
+/// Start a new conversation for a user by asking Cogito the initial question.
+///
+/// This is not an API path but the shared path behind every way of creating a conversation.
+pub(crate) async fn start_conversation(
+    user: &User,
+    conversation_info: CreateConversationRequest,
+    request_id: RequestId,
+    db: &PgPool,
+    services: &AgentServices,
+) -> Result<i32, HttpResponse> {
+    let answered = answer_conversation(user, conversation_info, request_id, db, services).await?;
+
+    let result: Result<i32, Error> = async {
+        let mut tx = db.begin().await?;
+        let conversation_id = answered.store(&mut tx, user).await?;
+        tx.commit().await?;
+        Ok(conversation_id)
+    }
+    .await;
+
+    let conversation_id = result.map_err(|e| store_error(e, user))?;
+    answered.done(conversation_id);
+    Ok(conversation_id)
+}
+
+/// Ask Cogito the initial question of a new conversation, leaving storing it to the caller.
+pub(crate) async fn answer_conversation(
+    user: &User,
+    conversation_info: CreateConversationRequest,
+    request_id: RequestId,
+    db: &PgPool,
+    services: &AgentServices,
+) -> Result<AnsweredConversation, HttpResponse> {
+    // Subscribers are told if anything below fails.
+    let mut progress = services
+        .progress_hub
+        .clone()
+        .into_inner()
+        .start(conversation_info.progress_id, user.user_id);
+
+    if let Some(backend) = &conversation_info.agent_backend
+        && !services.agent.has_backend(backend)
+    {
+        return Err(HttpResponse::BadRequest().json(GenericResponse {
+            message: AGENT_UNKNOWN_BACKEND,
+        }));
+    }
+
+    let documents = attachable_documents(&conversation_info.document_ids, user, db).await?;
+
+    // Picked up front so the agent already knows the conversation while answering.
+    let pseudonym = Uuid::new_v4();
+    let context = CallContext {
+        request_id: request_id.0,
+        user: user.pseudonym,
+        conversation: pseudonym,
+        backend: conversation_info.agent_backend.clone(),
+    };
+
+    let conversation = ask_cogito(
+        user,
+        Question {
+            content: conversation_info.initial_message,
+            documents,
+            options: Some(conversation_info.options.into()),
+            // A new conversation has nothing before its first question.
+            history: Vec::new(),
+            summary: String::new(),
+        },
+        &context,
+        &mut progress,
+        db,
+        services,
+    )
+    .await?;
+
+    Ok(AnsweredConversation {
+        conversation,
+        pseudonym,
+        agent_backend: conversation_info.agent_backend,
+        document_ids: conversation_info.document_ids,
+        progress,
+    })
+}
+
+/// Ask Cogito a question, returning the validated answer with its trace and provenance filled in.
+///
+/// Progress is reported to `progress`, and failures are turned into the response to send.
+pub(crate) async fn ask_cogito(
+    user: &User,
+    question: Question,
+    context: &CallContext,
+    progress: &mut ProgressReporter,
+    db: &PgPool,
+    services: &AgentServices,
+) -> Result<AgentConversation, HttpResponse> {
+    let cogito_response = services
+        .answer_cache
+        .ask(
+            &services.agent,
+            db,
+            user,
+            question,
+            context,
+            |event| match event {
+                AgentEvent::Progress(step) => progress.step(step.into()),
+                AgentEvent::ApprovalRequested { request, backend } => {
+                    let action = services.action_registry.clone().into_inner().register(
+                        request,
+                        backend,
+                        user.user_id,
+                        context.conversation,
+                        services.agent.get_ref().clone(),
+                    );
+                    progress.approval_needed(action);
+                }
+            },
+        )
+        .await;
+
+    // Nothing can be approved once the agent is done.
+    services
+        .action_registry
+        .forget_conversation(context.conversation);
+
+    let cogito_response: Answer = match cogito_response {
+        Ok(answer) => answer,
+        Err(status) => {
+            error!(
+                "Failed to ask the Cogito agent for user {}: {}",
+                user.user_name, status
+            );
+
+            return Err(match status.code() {
+                tonic::Code::InvalidArgument => HttpResponse::BadRequest().json(GenericResponse {
+                    message: AGENT_REJECTED_QUESTION,
+                }),
+                // Every agent backend is down or temporarily disabled.
+                tonic::Code::Unavailable => {
+                    HttpResponse::ServiceUnavailable().json(GenericResponse {
+                        message: AGENT_FAILED_TO_COMMUNICATE,
+                    })
+                }
+                _ => HttpResponse::InternalServerError().json(GenericResponse {
+                    message: AGENT_FAILED_TO_COMMUNICATE,
+                }),
+            });
+        }
+    };
+
+    let mut agent_conversation = match parse_answer(&cogito_response) {
+        Ok(conversation) => conversation,
+        Err(e) => {
+            error!(
+                "Rejected answer from the Cogito agent for user {}: {}. Payload: {}",
+                user.user_name, e, cogito_response.content
+            );
+            return Err(HttpResponse::BadGateway().json(GenericResponse {
+                message: AGENT_INVALID_RESPONSE,
+            }));
+        }
+    };
+
+    // Validation guarantees the last message is the answer.
+    if let Some(answer) = agent_conversation.messages.last_mut() {
+        answer.trace = progress.trace();
+        answer.provenance = Provenance::of(&cogito_response, context.backend.clone());
+    }
+
+    Ok(agent_conversation)
 }
 
 /// Create a new conversation with Cogito.
@@ -47,8 +375,13 @@ pub struct CreateConversationResponse {
     request_body = CreateConversationRequest,
     responses(
         (status = 200, description = "Conversation created successfully.", body = CreateConversationResponse),
+        (status = 400, description = AGENT_REJECTED_QUESTION, body = GenericResponse),
+        (status = 400, description = AGENT_UNKNOWN_BACKEND, body = GenericResponse),
         (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Document not found.", body = GenericResponse),
         (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 502, description = AGENT_INVALID_RESPONSE, body = GenericResponse),
+        (status = 503, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
     )
 )]
 #[post("/create_conversation")]
@@ -56,7 +389,7 @@ pub async fn create_conversation(
     req: HttpRequest,
     info: Either<Json<CreateConversationRequest>, Form<CreateConversationRequest>>,
     db: Data<PgPool>,
-    cogito_agent: Data<CogitoAgent>,
+    services: AgentServices,
 ) -> impl Responder {
     // Make sure we are logged in before creating a conversation.
     let user = match validate_session(&req, db.get_ref()).await {
@@ -64,57 +397,30 @@ pub async fn create_conversation(
         Err(e) => return e,
     };
 
-    let conversation_info = info.into_inner();
-
-    let cogito_response: Answer = match cogito_agent
-        .get_client()
-        .ask(tonic::Request::new(Question {
-            content: conversation_info.initial_message,
-        }))
-        .await
-    {
-        Ok(response) => response.into_inner(),
-        Err(_) => {
-            return HttpResponse::InternalServerError().json(GenericResponse {
-                message: AGENT_FAILED_TO_COMMUNICATE,
-            });
-        }
-    };
-
-    let conversation_id = match sqlx::query_scalar!(
-        r#"
-        insert into conversations (user_id, conversation)
-        values ($1, $2) returning conversation_id
-        "#,
-        user.user_id,
-        serde_json::from_str::<Value>(&cogito_response.content).unwrap()
+    match start_conversation(
+        &user,
+        info.into_inner(),
+        RequestId::of(&req),
+        db.get_ref(),
+        &services,
     )
-    .fetch_one(db.get_ref())
     .await
     {
-        Ok(id) => id,
-        // This really shouldn't fail, but handle the error just in case.
-        Err(e) => {
-            error!(
-                "Failed to create new conversation for user {}: {}",
-                user.user_name, e
-            );
-            return HttpResponse::InternalServerError().json(GenericResponse {
-                message: SERVER_ERROR,
-            });
+        Ok(conversation_id) => {
+            HttpResponse::Ok().json(CreateConversationResponse { conversation_id })
         }
-    };
-
-    HttpResponse::Ok().json(CreateConversationResponse { conversation_id })
+        Err(e) => e,
+    }
 }
 
 /// Fetch a conversation by its ID, ensuring it belongs to the given user.
 ///
-/// This is not an API path but a shortcut for internal use.
-async fn fetch_conversation(
+/// This is not an API path but a shortcut for internal use. It accepts any executor so it can also
+/// be used inside a transaction.
+pub(crate) async fn fetch_conversation<'e>(
     conversation_id: i32,
     user: &User,
-    db: &PgPool,
+    db: impl PgExecutor<'e>,
 ) -> Result<Conversation, HttpResponse> {
     match sqlx::query_as!(
         Conversation,
@@ -176,14 +482,178 @@ pub async fn get_conversation(
         Err(e) => return e,
     };
 
-    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+    let mut conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
         Ok(convo) => convo,
         Err(e) => return e,
     };
 
+    // Which variant answered stays hidden until the comparison is voted on.
+    if let Err(e) = blind_unvoted(&mut conversation, db.get_ref()).await {
+        error!(
+            "Failed to check comparisons of conversation {} for user {}: {}",
+            conversation.conversation_id, user.user_name, e
+        );
+        return HttpResponse::InternalServerError().json(GenericResponse {
+            message: SERVER_ERROR,
+        });
+    }
+
     HttpResponse::Ok().json(conversation)
 }
 
+/// Ask a follow-up question in a conversation.
+///
+/// The earlier messages are sent along, cut down to fit the agent's context window with the
+/// configured strategy. A summary of older messages made on the way is stored with the conversation
+/// so it isn't redone for every question, and the answer records how the history was cut down.
+#[utoipa::path(
+    post,
+    path = "/conversation/{conversation_id}/messages",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the conversation to continue.")
+    ),
+    request_body = FollowUpRequest,
+    responses(
+        (status = 200, description = "Follow-up answered successfully.", body = CreateConversationResponse),
+        (status = 400, description = AGENT_REJECTED_QUESTION, body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+        (status = 404, description = "Conversation not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 502, description = AGENT_INVALID_RESPONSE, body = GenericResponse),
+        (status = 503, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+    )
+)]
+#[post("/conversation/{conversation_id}/messages")]
+pub async fn ask_follow_up(
+    conversation_id: Path<i32>,
+    req: HttpRequest,
+    info: Either<Json<FollowUpRequest>, Form<FollowUpRequest>>,
+    db: Data<PgPool>,
+    services: AgentServices,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    // Stored conversations were validated as answers, so this only fails on a corrupted row.
+    let messages = match serde_json::from_value::<AgentConversation>(conversation.conversation) {
+        Ok(stored) => stored.messages,
+        Err(e) => {
+            error!(
+                "Stored conversation {} of user {} is unreadable: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    };
+    // A summary that can't be read is made again.
+    let summary: Option<ConversationSummary> = conversation
+        .context_summary
+        .and_then(|summary| serde_json::from_value(summary).ok());
+
+    let info = info.into_inner();
+    let mut progress = services
+        .progress_hub
+        .clone()
+        .into_inner()
+        .start(info.progress_id, user.user_id);
+
+    let prepared = prepare_context(
+        &services.agent,
+        &services.context_config,
+        &messages,
+        summary.as_ref(),
+    )
+    .await;
+
+    let context = CallContext {
+        request_id: RequestId::of(&req).0,
+        user: user.pseudonym,
+        conversation: conversation.pseudonym,
+        backend: conversation.agent_backend,
+    };
+
+    // The agent answers with the new turn only, the history is ours.
+    let mut turn = match ask_cogito(
+        &user,
+        Question {
+            content: info.content,
+            documents: Vec::new(),
+            options: Some(info.options.into()),
+            history: prepared.history,
+            summary: prepared.summary,
+        },
+        &context,
+        &mut progress,
+        db.get_ref(),
+        &services,
+    )
+    .await
+    {
+        Ok(turn) => turn,
+        Err(e) => return e,
+    };
+
+    if let Some(answer) = turn.messages.last_mut() {
+        answer.context = Some(prepared.applied);
+    }
+
+    let conversation_id = conversation.conversation_id;
+    let result: Result<(), Error> = async {
+        let mut tx = db.begin().await?;
+
+        // Locked so follow-ups asked at the same time are appended one after the other.
+        let current = sqlx::query_scalar!(
+            "select conversation from conversations where conversation_id = $1 for update",
+            conversation_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut current: AgentConversation =
+            serde_json::from_value(current).map_err(|e| Error::Decode(Box::new(e)))?;
+        current.messages.append(&mut turn.messages);
+
+        sqlx::query!(
+            r#"
+            update conversations
+            set conversation = $1, context_summary = coalesce($2::json, context_summary)
+            where conversation_id = $3
+            "#,
+            sqlx::types::Json(&current) as _,
+            prepared.new_summary.as_ref().map(sqlx::types::Json) as _,
+            conversation_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_revision(&mut tx, conversation_id, RevisionKind::FollowedUp).await?;
+
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = result {
+        error!(
+            "Failed to store follow-up in conversation {} for user {}: {}",
+            conversation_id, user.user_name, e
+        );
+        return HttpResponse::InternalServerError().json(GenericResponse {
+            message: SERVER_ERROR,
+        });
+    }
+
+    progress.done(conversation_id);
+    HttpResponse::Ok().json(CreateConversationResponse { conversation_id })
+}
+
 /// Delete an existing conversation.
 #[utoipa::path(
     delete,
@@ -214,17 +684,26 @@ pub async fn delete_conversation(
         Err(e) => return e,
     };
 
-    match sqlx::query!(
-        r#"
-        delete from conversations
-        where conversation_id = $1
-        "#,
-        conversation.conversation_id
-    )
-    .execute(db.get_ref())
-    .await
-    {
-        Ok(_) => HttpResponse::Ok().json(GenericResponse {
+    let result: Result<(), Error> = async {
+        let mut tx = db.begin().await?;
+
+        record_revision(&mut tx, conversation.conversation_id, RevisionKind::Deleted).await?;
+        sqlx::query!(
+            r#"
+            delete from conversations
+            where conversation_id = $1
+            "#,
+            conversation.conversation_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(GenericResponse {
             message: "Conversation deleted successfully.",
         }),
         Err(e) => {
@@ -238,3 +717,78 @@ pub async fn delete_conversation(
         }
     }
 }
+
+/// Rename an existing conversation.
+#[utoipa::path(
+    put,
+    path = "/conversation/{conversation_id}/title",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the conversation to rename.")
+    ),
+    request_body = RenameConversationRequest,
+    responses(
+        (status = 200, description = "Conversation renamed successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Conversation not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+    ))]
+#[put("/conversation/{conversation_id}/title")]
+pub async fn rename_conversation(
+    conversation_id: Path<i32>,
+    req: HttpRequest,
+    info: Either<Json<RenameConversationRequest>, Form<RenameConversationRequest>>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    let RenameConversationRequest { conversation_title } = info.into_inner();
+
+    let result: Result<(), Error> = async {
+        let mut tx = db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            update conversations set conversation_title = $1
+            where conversation_id = $2
+            "#,
+            conversation_title,
+            conversation.conversation_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        record_revision(
+            &mut tx,
+            conversation.conversation_id,
+            RevisionKind::Renamed,
+        )
+        .await?;
+
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(GenericResponse {
+            message: "Conversation renamed successfully.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to rename conversation {} for user {}: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
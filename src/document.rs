@@ -0,0 +1,473 @@
+use crate::api_messages::{BAD_SESSION, FORBIDDEN, GenericResponse, SERVER_ERROR};
+use crate::conversation::fetch_conversation;
+use crate::login::validate_session;
+use crate::proto;
+use crate::user::User;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::web::{Bytes, Data, Path, PayloadConfig, Query};
+use actix_web::{HttpRequest, HttpResponse, Resource, Responder, delete, get, web};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgConnection, PgPool};
+use utoipa::ToSchema;
+
+// Users can upload source texts to give Cogito as context. Only the extracted text is kept, the
+// original file is discarded once it has been read.
+
+/// Largest document that can be uploaded, in bytes.
+const MAX_DOCUMENT_BYTES: usize = 10 * 1024 * 1024;
+
+/// The message returned when a document doesn't exist or belongs to someone else.
+pub(crate) static DOCUMENT_NOT_FOUND: &str = "Document not found.";
+
+/// A source text uploaded by a user.
+#[derive(Serialize, ToSchema)]
+pub struct Document {
+    pub(crate) document_id: i32,
+    pub(crate) user_id: i32,
+    /// The conversation this document was given to, if it has been used yet.
+    pub(crate) conversation_id: Option<i32>,
+    pub(crate) file_name: String,
+    /// Media type of the uploaded file, e.g. `application/pdf`.
+    pub(crate) media_type: String,
+    /// Text extracted from the uploaded file.
+    pub(crate) content: String,
+    #[schema(value_type = String, format = "date-time")]
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+/// A document without its contents, used when listing documents.
+#[derive(Serialize, ToSchema)]
+pub struct DocumentInfo {
+    document_id: i32,
+    conversation_id: Option<i32>,
+    file_name: String,
+    media_type: String,
+    /// Length of the extracted text in characters.
+    content_length: i32,
+    #[schema(value_type = String, format = "date-time")]
+    created_at: DateTime<Utc>,
+}
+
+/// Query parameters when uploading a document.
+#[derive(Deserialize)]
+pub struct UploadDocumentQuery {
+    file_name: String,
+}
+
+/// Media types that text can be extracted from.
+const SUPPORTED_MEDIA_TYPES: [&str; 3] = ["text/plain", "text/markdown", "application/pdf"];
+
+/// Extract the text of an uploaded file based on its media type.
+///
+/// Postgres can't store NUL characters in text. PDFs may contain them as extraction artifacts,
+/// so they are dropped there, while a text document containing one probably isn't text at all.
+///
+/// Returns the message to respond with if the file can't be read.
+fn extract_text(media_type: &str, body: &[u8]) -> Result<String, &'static str> {
+    match media_type {
+        "application/pdf" => pdf_extract::extract_text_from_mem(body)
+            .map(|text| text.replace('\0', ""))
+            .map_err(|e| {
+                error!("Failed to extract text from uploaded PDF: {}", e);
+                "Unable to read PDF document."
+            }),
+        _ => {
+            let text = String::from_utf8(body.to_vec())
+                .map_err(|_| "Text documents must be UTF-8 encoded.")?;
+            if text.contains('\0') {
+                return Err("Text documents must not contain NUL characters.");
+            }
+            Ok(text)
+        }
+    }
+}
+
+/// Load documents the user wants to give to a new conversation.
+///
+/// Every document must belong to the user and not have been given to another conversation yet.
+pub(crate) async fn attachable_documents(
+    document_ids: &[i32],
+    user: &User,
+    db: &PgPool,
+) -> Result<Vec<proto::Document>, HttpResponse> {
+    if document_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The same document may be listed twice, it is still only given once.
+    let mut document_ids = document_ids.to_vec();
+    document_ids.sort_unstable();
+    document_ids.dedup();
+
+    let documents = sqlx::query_as!(
+        Document,
+        r#"
+        select * from documents
+        where document_id = any($1) and user_id = $2 and conversation_id is null
+        order by document_id
+        "#,
+        &document_ids,
+        user.user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| {
+        error!(
+            "Failed to load documents for user {}: {}",
+            user.user_name, e
+        );
+        HttpResponse::InternalServerError().json(GenericResponse {
+            message: SERVER_ERROR,
+        })
+    })?;
+
+    if documents.len() != document_ids.len() {
+        return Err(HttpResponse::NotFound().json(GenericResponse {
+            message: DOCUMENT_NOT_FOUND,
+        }));
+    }
+
+    Ok(documents
+        .into_iter()
+        .map(|document| proto::Document {
+            name: document.file_name,
+            media_type: document.media_type,
+            content: document.content,
+        })
+        .collect())
+}
+
+/// Mark documents as given to a conversation.
+///
+/// Should be called in the same transaction that creates the conversation. Fails with
+/// `RowNotFound` if a document was given to another conversation since it was loaded, so that
+/// transaction is rolled back.
+pub(crate) async fn attach_documents(
+    conn: &mut PgConnection,
+    document_ids: &[i32],
+    conversation_id: i32,
+    user: &User,
+) -> Result<(), Error> {
+    let mut document_ids = document_ids.to_vec();
+    document_ids.sort_unstable();
+    document_ids.dedup();
+
+    let result = sqlx::query!(
+        r#"
+        update documents set conversation_id = $1
+        where document_id = any($2) and user_id = $3 and conversation_id is null
+        "#,
+        conversation_id,
+        &document_ids,
+        user.user_id
+    )
+    .execute(conn)
+    .await?;
+
+    if result.rows_affected() != document_ids.len() as u64 {
+        return Err(Error::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// The route of `upload_document`, the only one accepting bodies up to `MAX_DOCUMENT_BYTES`.
+pub fn upload_document_resource() -> Resource {
+    web::resource("/documents")
+        .app_data(PayloadConfig::new(MAX_DOCUMENT_BYTES))
+        .route(web::post().to(upload_document))
+}
+
+/// Upload a source text.
+///
+/// The request body is the raw file, with its `Content-Type` set to `text/plain`, `text/markdown`,
+/// or `application/pdf`. The returned document ID can be given to `/create_conversation` to let
+/// Cogito use the text as context.
+#[utoipa::path(
+    post,
+    path = "/documents",
+    params(
+        ("file_name" = String, Query, description = "Name of the uploaded file.")
+    ),
+    request_body(content = String, description = "The raw file contents.", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Document uploaded successfully.", body = DocumentInfo),
+        (status = 400, description = "The document could not be read.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 415, description = "Unsupported document type.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+pub async fn upload_document(
+    req: HttpRequest,
+    query: Query<UploadDocumentQuery>,
+    body: Bytes,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    // Only the essence of the media type matters, e.g. ignore `; charset=utf-8`.
+    let media_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    // Postgres can't store it, so this would otherwise fail as a server error.
+    if query.file_name.contains('\0') {
+        return HttpResponse::BadRequest().json(GenericResponse {
+            message: "File names must not contain NUL characters.",
+        });
+    }
+
+    if !SUPPORTED_MEDIA_TYPES.contains(&media_type.as_str()) {
+        return HttpResponse::UnsupportedMediaType().json(GenericResponse {
+            message: "Only plain text, Markdown, and PDF documents are supported.",
+        });
+    }
+
+    // PDF extraction can take a while, keep it off the worker thread.
+    let extraction = {
+        let media_type = media_type.clone();
+        actix_web::web::block(move || extract_text(&media_type, &body)).await
+    };
+
+    let content = match extraction {
+        Ok(Ok(content)) => content,
+        Ok(Err(message)) => return HttpResponse::BadRequest().json(GenericResponse { message }),
+        Err(e) => {
+            error!("Document extraction task failed: {}", e);
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    };
+
+    match sqlx::query_as!(
+        DocumentInfo,
+        r#"
+        insert into documents (user_id, file_name, media_type, content)
+        values ($1, $2, $3, $4)
+        returning document_id, conversation_id, file_name, media_type,
+                  length(content) as "content_length!", created_at
+        "#,
+        user.user_id,
+        query.file_name,
+        media_type,
+        content
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(document) => HttpResponse::Ok().json(document),
+        Err(e) => {
+            error!(
+                "Failed to store document for user {}: {}",
+                user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Get an uploaded document along with its extracted text.
+#[utoipa::path(
+    get,
+    path = "/documents/{document_id}",
+    params(
+        ("document_id" = i32, Path, description = "The ID of the document to retrieve.")
+    ),
+    responses(
+        (status = 200, description = "Document retrieved successfully.", body = Document),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = DOCUMENT_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[get("/documents/{document_id}")]
+pub async fn get_document(
+    document_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match sqlx::query_as!(
+        Document,
+        "select * from documents where document_id = $1 and user_id = $2",
+        *document_id,
+        user.user_id
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(document) => HttpResponse::Ok().json(document),
+        Err(Error::RowNotFound) => HttpResponse::NotFound().json(GenericResponse {
+            message: DOCUMENT_NOT_FOUND,
+        }),
+        Err(e) => {
+            error!(
+                "Failed to retrieve document {} for user {}: {}",
+                *document_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Delete an uploaded document.
+#[utoipa::path(
+    delete,
+    path = "/documents/{document_id}",
+    params(
+        ("document_id" = i32, Path, description = "The ID of the document to delete.")
+    ),
+    responses(
+        (status = 200, description = "Document deleted successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = DOCUMENT_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[delete("/documents/{document_id}")]
+pub async fn delete_document(
+    document_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match sqlx::query!(
+        "delete from documents where document_id = $1 and user_id = $2",
+        *document_id,
+        user.user_id
+    )
+    .execute(db.get_ref())
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::NotFound().json(GenericResponse {
+                message: DOCUMENT_NOT_FOUND,
+            })
+        }
+        Ok(_) => HttpResponse::Ok().json(GenericResponse {
+            message: "Document deleted successfully.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to delete document {} for user {}: {}",
+                *document_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// List the documents given to a conversation.
+#[utoipa::path(
+    get,
+    path = "/conversation/{conversation_id}/documents",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the conversation.")
+    ),
+    responses(
+        (status = 200, description = "Documents retrieved successfully.", body = Vec<DocumentInfo>),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Conversation not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+    ))]
+#[get("/conversation/{conversation_id}/documents")]
+pub async fn get_conversation_documents(
+    conversation_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    match sqlx::query_as!(
+        DocumentInfo,
+        r#"
+        select document_id, conversation_id, file_name, media_type,
+               length(content) as "content_length!", created_at
+        from documents
+        where conversation_id = $1
+        order by document_id
+        "#,
+        conversation.conversation_id
+    )
+    .fetch_all(db.get_ref())
+    .await
+    {
+        Ok(documents) => HttpResponse::Ok().json(documents),
+        Err(e) => {
+            error!(
+                "Failed to retrieve documents of conversation {} for user {}: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract_text;
+
+    /// Test that text documents are read as UTF-8.
+    #[test]
+    fn test_extract_text() {
+        assert_eq!(
+            extract_text("text/markdown", "# Ἠθικὰ Νικομάχεια".as_bytes()).unwrap(),
+            "# Ἠθικὰ Νικομάχεια"
+        );
+        assert!(extract_text("text/plain", &[0xff, 0xfe, 0x00]).is_err());
+    }
+
+    /// Test that text documents containing NUL characters are rejected instead of failing to store.
+    #[test]
+    fn test_extract_nul() {
+        assert_eq!(
+            extract_text("text/plain", b"Nicomachean\0Ethics").unwrap_err(),
+            "Text documents must not contain NUL characters."
+        );
+    }
+
+    /// Test that a file claiming to be a PDF but isn't one is rejected.
+    #[test]
+    fn test_extract_invalid_pdf() {
+        assert_eq!(
+            extract_text("application/pdf", b"not a pdf").unwrap_err(),
+            "Unable to read PDF document."
+        );
+    }
+}
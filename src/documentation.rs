@@ -1,16 +1,66 @@
 use utoipa::OpenApi;
 
 // For some reason it wants the full qualified paths with the "__" prefix as shown.
+use crate::action;
+use crate::action::__path_get_pending_actions;
+use crate::action::__path_resolve_action;
+use crate::agent;
+use crate::agent_info;
+use crate::agent_info::__path_agent_capabilities;
+use crate::agent_info::__path_agent_status;
+use crate::annotation;
+use crate::annotation::__path_create_annotation;
+use crate::annotation::__path_delete_annotation;
+use crate::annotation::__path_get_annotations;
+use crate::annotation::__path_update_annotation;
+use crate::answer;
+use crate::answer_cache;
+use crate::answer_cache::__path_answer_cache_stats;
 use crate::api_messages;
+use crate::bulk;
+use crate::bulk::__path_bulk_conversations;
+use crate::compare;
+use crate::compare::__path_create_comparison;
+use crate::compare::__path_get_comparison;
+use crate::compare::__path_vote_comparison;
+use crate::context;
 use crate::conversation;
+use crate::conversation::__path_ask_follow_up;
 use crate::conversation::__path_create_conversation;
 use crate::conversation::__path_delete_conversation;
 use crate::conversation::__path_get_conversation;
+use crate::conversation::__path_rename_conversation;
+use crate::document;
+use crate::document::__path_delete_document;
+use crate::document::__path_get_conversation_documents;
+use crate::document::__path_get_document;
+use crate::document::__path_upload_document;
+use crate::health;
+use crate::health::__path_healthz;
+use crate::health::__path_readyz;
 use crate::login;
 use crate::login::__path_login_request;
+use crate::progress;
+use crate::progress::__path_get_progress;
 use crate::register;
 use crate::register::__path_register_request;
+use crate::revision;
+use crate::revision::__path_get_revision;
+use crate::revision::__path_get_revisions;
+use crate::session;
+use crate::session::__path_delete_session;
+use crate::session::__path_get_sessions;
+use crate::session::__path_logout;
+use crate::session::__path_logout_all;
+use crate::template;
+use crate::template::__path_conversation_from_template;
+use crate::template::__path_create_template;
+use crate::template::__path_delete_template;
+use crate::template::__path_get_template;
+use crate::template::__path_get_templates;
+use crate::template::__path_update_template;
 use crate::user;
+use crate::user::__path_set_answer_cache;
 use crate::user::__path_user_by_id;
 
 /// This module is for utoipa's autogenerated OpenAPI documentation.
@@ -18,23 +68,114 @@ use crate::user::__path_user_by_id;
 #[derive(OpenApi)]
 #[openapi(
     paths(
+        healthz,
+        readyz,
+        agent_status,
+        agent_capabilities,
+        answer_cache_stats,
         login_request,
+        logout,
+        logout_all,
+        get_sessions,
+        delete_session,
         register_request,
         user_by_id,
+        set_answer_cache,
         create_conversation,
         get_conversation,
         delete_conversation,
+        rename_conversation,
+        ask_follow_up,
+        bulk_conversations,
+        get_revisions,
+        get_revision,
+        get_templates,
+        create_template,
+        get_template,
+        update_template,
+        delete_template,
+        conversation_from_template,
+        get_progress,
+        get_pending_actions,
+        resolve_action,
+        create_comparison,
+        get_comparison,
+        vote_comparison,
+        upload_document,
+        get_document,
+        delete_document,
+        get_conversation_documents,
+        get_annotations,
+        create_annotation,
+        update_annotation,
+        delete_annotation,
     ),
     components(
         schemas(
+            agent::AgentHealth,
+            agent::BackendStatus,
+            agent::BreakerStatus,
+            agent_info::AgentStatusResponse,
+            agent_info::CapabilitiesResponse,
+            agent_info::AgentModel,
+            agent_info::AgentCorpus,
+            agent_info::AgentTool,
+            agent_info::AgentOptionRange,
+            agent_info::AgentOptions,
+            answer_cache::AnswerCacheStats,
+            progress::ProgressEvent,
+            action::PendingActionInfo,
+            action::ActionDecisionRequest,
+            compare::CompareVariant,
+            compare::CompareRequest,
+            compare::CompareResponse,
+            compare::Vote,
+            compare::VoteRequest,
+            compare::Comparison,
+            answer::ResearchStep,
+            answer::ResearchStage,
+            user::AnswerCacheSetting,
+            health::AgentReadiness,
+            health::DatabaseReadiness,
+            health::ReadinessResponse,
             login::LoginInformation,
+            login::LoginResponse,
+            session::Session,
             api_messages::GenericResponse,
             register::RegisterInformation,
             register::RegisterResponse,
             user::User,
             conversation::Conversation,
+            answer::AgentConversation,
+            answer::AgentMessage,
+            answer::Role,
+            answer::Citation,
+            answer::Provenance,
             conversation::CreateConversationRequest,
             conversation::CreateConversationResponse,
+            conversation::RenameConversationRequest,
+            conversation::FollowUpRequest,
+            context::AppliedContext,
+            context::ContextStrategy,
+            bulk::BulkAction,
+            bulk::BulkRequest,
+            bulk::BulkStatus,
+            bulk::BulkResult,
+            bulk::BulkResponse,
+            revision::RevisionSummary,
+            revision::ConversationRevision,
+            template::PromptTemplate,
+            template::TemplateResponse,
+            template::CreateTemplateRequest,
+            template::UpdateTemplateRequest,
+            template::FromTemplateRequest,
+            template::MissingVariablesResponse,
+            document::Document,
+            document::DocumentInfo,
+            annotation::Annotation,
+            annotation::AnchoredAnnotation,
+            annotation::CreateAnnotationRequest,
+            annotation::UpdateAnnotationRequest,
         )
     ),
     tags(
@@ -0,0 +1,306 @@
+//! Regression checks for the Cogito agent.
+//!
+//! Before upgrading the agent, a benchmark of questions is run against the current and the new
+//! version and the two runs are compared. Stored conversations can be turned into a run as well,
+//! so the new version can also be checked against what users were actually answered.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+use crate::agent::{CallContext, CogitoAgent};
+use crate::answer::{AgentConversation, Role, parse_answer};
+use crate::proto::{AskOptions, Question};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A question to ask in every run.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BenchmarkQuestion {
+    /// Stable identifier, used to match answers between runs.
+    pub id: String,
+    pub content: String,
+    /// Model to answer with, the agent's default if missing.
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// How the agent answered one question.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EvalRecord {
+    pub id: String,
+    pub question: String,
+    /// The agent's reply, missing if the call failed or the answer was rejected.
+    pub answer: Option<String>,
+    pub error: Option<String>,
+    /// How long the agent took, unknown for stored conversations.
+    pub latency_ms: Option<u64>,
+    pub citations: usize,
+    pub agent_version: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Every answer from one run of a benchmark.
+#[derive(Serialize, Deserialize)]
+pub struct EvalRun {
+    pub started_at: DateTime<Utc>,
+    pub records: Vec<EvalRecord>,
+}
+
+/// How the answer to a question changed between two runs.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffStatus {
+    Unchanged,
+    /// Answered both times, but differently.
+    Changed,
+    /// Failed before, answered now.
+    Fixed,
+    /// Answered before, fails now.
+    Broken,
+    /// Failed both times.
+    Failing,
+    /// Only in the candidate run.
+    Added,
+    /// Only in the baseline run.
+    Removed,
+}
+
+/// The comparison of one question between two runs.
+#[derive(Serialize)]
+pub struct DiffEntry {
+    pub id: String,
+    pub status: DiffStatus,
+    pub baseline_latency_ms: Option<u64>,
+    pub candidate_latency_ms: Option<u64>,
+    pub baseline_citations: Option<usize>,
+    pub candidate_citations: Option<usize>,
+}
+
+/// The comparison of two runs, in the order of the candidate run.
+#[derive(Serialize)]
+pub struct EvalDiff {
+    pub entries: Vec<DiffEntry>,
+}
+
+/// Ask every benchmark question, one after the other so latencies aren't skewed.
+pub async fn run_benchmark(agent: &CogitoAgent, questions: &[BenchmarkQuestion]) -> EvalRun {
+    let started_at = Utc::now();
+    // All questions of a run are asked by the same pretend user.
+    let user = Uuid::new_v4();
+    let mut records = Vec::with_capacity(questions.len());
+
+    for benchmark in questions {
+        let question = Question {
+            content: benchmark.content.clone(),
+            documents: Vec::new(),
+            options: Some(AskOptions {
+                model: benchmark.model.clone().unwrap_or_default(),
+                ..AskOptions::default()
+            }),
+            history: Vec::new(),
+            summary: String::new(),
+        };
+        let context = CallContext {
+            request_id: format!("eval-{}", benchmark.id),
+            user,
+            conversation: Uuid::new_v4(),
+            backend: None,
+        };
+
+        let start = Instant::now();
+        let result = agent.ask(question, &context).await;
+        let latency_ms = Some(start.elapsed().as_millis() as u64);
+
+        let mut record = EvalRecord {
+            id: benchmark.id.clone(),
+            question: benchmark.content.clone(),
+            answer: None,
+            error: None,
+            latency_ms,
+            citations: 0,
+            agent_version: None,
+            model: None,
+        };
+
+        match result {
+            Ok(answer) => {
+                // Agents that predate reporting these leave them empty.
+                record.agent_version =
+                    Some(answer.agent_version.clone()).filter(|version| !version.is_empty());
+                record.model = Some(answer.model.clone()).filter(|model| !model.is_empty());
+                match parse_answer(&answer) {
+                    Ok(conversation) => fill_reply(&mut record, &conversation),
+                    Err(e) => record.error = Some(e.to_string()),
+                }
+            }
+            Err(status) => record.error = Some(status.to_string()),
+        }
+
+        records.push(record);
+    }
+
+    EvalRun {
+        started_at,
+        records,
+    }
+}
+
+/// Turn a stored conversation into a record of how it was originally answered.
+///
+/// Returns `None` for conversations that don't start with a question.
+pub fn stored_record(id: String, conversation: &AgentConversation) -> Option<EvalRecord> {
+    let question = conversation
+        .messages
+        .iter()
+        .find(|message| message.role == Role::User)?;
+
+    let mut record = EvalRecord {
+        id,
+        question: question.content.clone(),
+        answer: None,
+        error: None,
+        latency_ms: None,
+        citations: 0,
+        agent_version: None,
+        model: None,
+    };
+    fill_reply(&mut record, conversation);
+
+    Some(record)
+}
+
+/// Record the reply to the question, which is the first message from the agent.
+fn fill_reply(record: &mut EvalRecord, conversation: &AgentConversation) {
+    let reply = conversation
+        .messages
+        .iter()
+        .find(|message| message.role == Role::Assistant);
+
+    let Some(reply) = reply else {
+        record.error = Some("no reply from the agent".to_string());
+        return;
+    };
+
+    record.answer = Some(reply.content.clone());
+    record.citations = reply.citations.len();
+    if let Some(provenance) = &reply.provenance {
+        record.agent_version = Some(provenance.agent_version.clone());
+        record.model = Some(provenance.model.clone());
+    }
+}
+
+/// Compare a candidate run against a baseline, matching questions by ID.
+pub fn diff(baseline: &EvalRun, candidate: &EvalRun) -> EvalDiff {
+    let baseline_records: HashMap<&str, &EvalRecord> = baseline
+        .records
+        .iter()
+        .map(|record| (record.id.as_str(), record))
+        .collect();
+
+    let mut entries: Vec<DiffEntry> = candidate
+        .records
+        .iter()
+        .map(|record| {
+            let before = baseline_records.get(record.id.as_str());
+            let status = match before {
+                None => DiffStatus::Added,
+                Some(before) => match (&before.answer, &record.answer) {
+                    (Some(old), Some(new)) if old == new => DiffStatus::Unchanged,
+                    (Some(_), Some(_)) => DiffStatus::Changed,
+                    (None, Some(_)) => DiffStatus::Fixed,
+                    (Some(_), None) => DiffStatus::Broken,
+                    (None, None) => DiffStatus::Failing,
+                },
+            };
+
+            DiffEntry {
+                id: record.id.clone(),
+                status,
+                baseline_latency_ms: before.and_then(|before| before.latency_ms),
+                candidate_latency_ms: record.latency_ms,
+                baseline_citations: before.map(|before| before.citations),
+                candidate_citations: Some(record.citations),
+            }
+        })
+        .collect();
+
+    let candidate_ids: Vec<&str> = candidate
+        .records
+        .iter()
+        .map(|record| record.id.as_str())
+        .collect();
+    entries.extend(
+        baseline
+            .records
+            .iter()
+            .filter(|record| !candidate_ids.contains(&record.id.as_str()))
+            .map(|record| DiffEntry {
+                id: record.id.clone(),
+                status: DiffStatus::Removed,
+                baseline_latency_ms: record.latency_ms,
+                candidate_latency_ms: None,
+                baseline_citations: Some(record.citations),
+                candidate_citations: None,
+            }),
+    );
+
+    EvalDiff { entries }
+}
+
+impl EvalDiff {
+    /// How many questions ended up in a status.
+    pub fn count(&self, status: DiffStatus) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count()
+    }
+
+    /// Whether the candidate fails any question the baseline answered.
+    pub fn has_regressions(&self) -> bool {
+        self.count(DiffStatus::Broken) > 0
+    }
+}
+
+/// Format an optional number, or "-" if it is missing.
+fn or_dash<T: fmt::Display>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+impl fmt::Display for EvalDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<24} {:<10} {:>10} {:>10} {:>9} {:>9}",
+            "question", "status", "ms before", "ms after", "cites bef", "cites aft"
+        )?;
+
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:<24} {:<10} {:>10} {:>10} {:>9} {:>9}",
+                entry.id,
+                format!("{:?}", entry.status).to_lowercase(),
+                or_dash(entry.baseline_latency_ms),
+                or_dash(entry.candidate_latency_ms),
+                or_dash(entry.baseline_citations),
+                or_dash(entry.candidate_citations),
+            )?;
+        }
+
+        writeln!(f)?;
+        write!(
+            f,
+            "{} unchanged, {} changed, {} fixed, {} broken, {} failing, {} added, {} removed",
+            self.count(DiffStatus::Unchanged),
+            self.count(DiffStatus::Changed),
+            self.count(DiffStatus::Fixed),
+            self.count(DiffStatus::Broken),
+            self.count(DiffStatus::Failing),
+            self.count(DiffStatus::Added),
+            self.count(DiffStatus::Removed),
+        )
+    }
+}
@@ -0,0 +1,224 @@
+use crate::conversation::Conversation;
+use crate::login::{SessionError, peek_session_user};
+use crate::user::User;
+use log::error;
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use pb::cogito_api_server::{CogitoApi, CogitoApiServer};
+use pb::{
+    ConversationInfo, GetConversationRequest, ListConversationsRequest, ListConversationsResponse,
+    UserRef, ValidateSessionRequest, get_conversation_request, user_ref,
+};
+
+// Internal tools and the agent itself read conversation history over gRPC instead of the cookie
+// authenticated REST API. They are trusted services, so this is authenticated with a shared secret
+// and can read every user's data. It only reads, changes still go through the REST API.
+
+/// Types generated from `proto/cogito_api.proto`.
+pub mod pb {
+    tonic::include_proto!("cogito_api");
+}
+
+/// gRPC service exposing the API's data.
+pub struct ApiService {
+    db: PgPool,
+    /// The expected `authorization` metadata value.
+    authorization: String,
+}
+
+fn database_error(e: sqlx::Error) -> Status {
+    error!("Database error while serving gRPC: {}", e);
+    Status::internal("Database error.")
+}
+
+fn parse_pseudonym(pseudonym: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(pseudonym).map_err(|_| Status::invalid_argument("Invalid pseudonym."))
+}
+
+impl From<User> for pb::User {
+    fn from(user: User) -> Self {
+        pb::User {
+            user_id: user.user_id,
+            user_name: user.user_name,
+            user_email: user.user_email,
+            verified: user.verified,
+            admin: user.admin,
+            pseudonym: user.pseudonym.to_string(),
+            last_login: user.user_last_login.timestamp(),
+        }
+    }
+}
+
+impl From<Conversation> for pb::Conversation {
+    fn from(conversation: Conversation) -> Self {
+        pb::Conversation {
+            content: conversation.conversation.to_string(),
+            info: Some(ConversationInfo {
+                conversation_id: conversation.conversation_id,
+                user_id: conversation.user_id,
+                title: conversation.conversation_title,
+                created_at: conversation.created_at.timestamp(),
+                archived: conversation.archived,
+                folder: conversation.folder.unwrap_or_default(),
+                tags: conversation.tags,
+                pseudonym: conversation.pseudonym.to_string(),
+            }),
+        }
+    }
+}
+
+impl ApiService {
+    pub fn new(db: PgPool, token: &str) -> Self {
+        ApiService {
+            db,
+            authorization: format!("Bearer {}", token),
+        }
+    }
+
+    /// Reject calls that don't carry the shared secret.
+    ///
+    /// Compared in constant time, so the secret can't be guessed byte by byte from response times.
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let expected = self.authorization.as_bytes();
+        match request.metadata().get("authorization") {
+            Some(value) if bool::from(value.as_bytes().ct_eq(expected)) => Ok(()),
+            _ => Err(Status::unauthenticated("Missing or wrong API token.")),
+        }
+    }
+
+    async fn find_user(&self, user: Option<UserRef>) -> Result<User, Status> {
+        let user = match user.and_then(|user| user.user) {
+            Some(user_ref::User::UserId(user_id)) => {
+                sqlx::query_as!(User, "select * from users where user_id = $1", user_id)
+                    .fetch_optional(&self.db)
+                    .await
+            }
+            Some(user_ref::User::Pseudonym(pseudonym)) => {
+                let pseudonym = parse_pseudonym(&pseudonym)?;
+                sqlx::query_as!(User, "select * from users where pseudonym = $1", pseudonym)
+                    .fetch_optional(&self.db)
+                    .await
+            }
+            None => return Err(Status::invalid_argument("A user is required.")),
+        };
+
+        user.map_err(database_error)?
+            .ok_or_else(|| Status::not_found("User not found."))
+    }
+}
+
+#[tonic::async_trait]
+impl CogitoApi for ApiService {
+    async fn get_user(&self, request: Request<UserRef>) -> Result<Response<pb::User>, Status> {
+        self.authenticate(&request)?;
+        let user = self.find_user(Some(request.into_inner())).await?;
+
+        Ok(Response::new(user.into()))
+    }
+
+    async fn validate_session(
+        &self,
+        request: Request<ValidateSessionRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        self.authenticate(&request)?;
+        let login_id = Uuid::parse_str(&request.into_inner().login_id)
+            .map_err(|_| Status::invalid_argument("Invalid login_id."))?;
+
+        // Checking a session for someone else mustn't keep it alive.
+        match peek_session_user(login_id, &self.db).await {
+            Ok(user) => Ok(Response::new(user.into())),
+            Err(SessionError::Invalid) => Err(Status::unauthenticated(
+                "Invalid session. Please login again.",
+            )),
+            Err(SessionError::Database(e)) => Err(database_error(e)),
+        }
+    }
+
+    async fn list_conversations(
+        &self,
+        request: Request<ListConversationsRequest>,
+    ) -> Result<Response<ListConversationsResponse>, Status> {
+        self.authenticate(&request)?;
+        let request = request.into_inner();
+        let user = self.find_user(request.user).await?;
+
+        // A null limit returns every row.
+        let limit = (request.limit > 0).then_some(i64::from(request.limit));
+
+        let conversations = sqlx::query_as!(
+            Conversation,
+            r#"
+            select * from conversations
+            where user_id = $1 and ($2 or not archived)
+            order by created_at desc
+            limit $3
+            "#,
+            user.user_id,
+            request.include_archived,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(database_error)?;
+
+        Ok(Response::new(ListConversationsResponse {
+            conversations: conversations
+                .into_iter()
+                .filter_map(|conversation| pb::Conversation::from(conversation).info)
+                .collect(),
+        }))
+    }
+
+    async fn get_conversation(
+        &self,
+        request: Request<GetConversationRequest>,
+    ) -> Result<Response<pb::Conversation>, Status> {
+        self.authenticate(&request)?;
+
+        let conversation = match request.into_inner().conversation {
+            Some(get_conversation_request::Conversation::ConversationId(conversation_id)) => {
+                sqlx::query_as!(
+                    Conversation,
+                    "select * from conversations where conversation_id = $1",
+                    conversation_id
+                )
+                .fetch_optional(&self.db)
+                .await
+            }
+            Some(get_conversation_request::Conversation::Pseudonym(pseudonym)) => {
+                let pseudonym = parse_pseudonym(&pseudonym)?;
+                sqlx::query_as!(
+                    Conversation,
+                    "select * from conversations where pseudonym = $1",
+                    pseudonym
+                )
+                .fetch_optional(&self.db)
+                .await
+            }
+            None => return Err(Status::invalid_argument("A conversation is required.")),
+        };
+
+        let conversation = conversation
+            .map_err(database_error)?
+            .ok_or_else(|| Status::not_found("Conversation not found."))?;
+
+        Ok(Response::new(conversation.into()))
+    }
+}
+
+/// Serve the gRPC API on an already bound listener until the process exits.
+pub async fn serve(
+    listener: TcpListener,
+    service: ApiService,
+) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(CogitoApiServer::new(service))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use crate::agent::CogitoAgent;
+use crate::api_messages::GenericResponse;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, Responder, get};
+use log::error;
+use serde::Serialize;
+use sqlx::{Connection, PgPool};
+use utoipa::ToSchema;
+
+// These endpoints are for Docker Compose and the orchestrator rather than users, so they don't
+// require a session.
+
+/// How long the database gets to answer a readiness check.
+const DATABASE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// JSON response describing whether the API can serve requests.
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    /// Whether the database is ready, without which nothing works.
+    ready: bool,
+    /// Whether the API is ready but questions to the agent will fail.
+    degraded: bool,
+    database: DatabaseReadiness,
+    agent: AgentReadiness,
+}
+
+/// State of the PostgreSQL connection pool.
+#[derive(Serialize, ToSchema)]
+pub struct DatabaseReadiness {
+    ready: bool,
+    /// Connections currently open, idle or in use.
+    pool_size: u32,
+    idle_connections: usize,
+    /// Why the database isn't ready, if it isn't.
+    error: Option<&'static str>,
+}
+
+/// State of the connection to the Cogito agent.
+///
+/// Details about each backend are in `/agent/status`, which requires a session.
+#[derive(Serialize, ToSchema)]
+pub struct AgentReadiness {
+    /// Whether at least one backend can take requests.
+    ready: bool,
+}
+
+/// Report that the process is alive.
+///
+/// This doesn't check any dependencies, a failing database shouldn't get the API restarted.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "The API process is running.", body = GenericResponse),
+    )
+)]
+#[get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(GenericResponse { message: "OK." })
+}
+
+/// Report whether the API is usable, with details for each dependency.
+///
+/// Only the database decides readiness. Everything but asking questions keeps working while the
+/// agent is down, so that is reported as degraded instead of taking the whole API out of rotation.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "The API is ready to serve requests.", body = ReadinessResponse),
+        (status = 503, description = "The database isn't ready.", body = ReadinessResponse),
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz(db: Data<PgPool>, cogito_agent: Data<CogitoAgent>) -> impl Responder {
+    let db = db.get_ref();
+
+    let ping = tokio::time::timeout(DATABASE_CHECK_TIMEOUT, async {
+        let mut connection = db.acquire().await?;
+        connection.ping().await
+    })
+    .await;
+
+    let error = match ping {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            error!("Readiness check failed to reach PostgreSQL: {}", e);
+            Some("Unable to reach the database.")
+        }
+        Err(_) => Some("The database took too long to respond."),
+    };
+
+    let database = DatabaseReadiness {
+        ready: error.is_none(),
+        pool_size: db.size(),
+        idle_connections: db.num_idle(),
+        error,
+    };
+
+    let agent = AgentReadiness {
+        ready: cogito_agent.is_ready(),
+    };
+
+    let ready = database.ready;
+    let mut response = if ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    response.json(ReadinessResponse {
+        ready,
+        degraded: !agent.ready,
+        database,
+        agent,
+    })
+}
@@ -0,0 +1,8 @@
+//! Pieces of the Cogito API shared between its binaries and tests.
+
+pub mod agent;
+pub mod answer;
+pub mod context;
+pub mod eval;
+pub mod mock_agent;
+pub mod proto;
@@ -3,12 +3,14 @@ use crate::api_messages::{
 };
 use crate::user::User;
 use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
 use actix_web::web::{Data, Form, Json};
 use actix_web::{Either, HttpRequest, HttpResponse, Responder, cookie, post};
 use argon2::Argon2;
 use chrono::{Duration, Utc};
+use log::error;
 use password_hash::{PasswordHash, PasswordVerifier};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{Error, PgPool};
 use utoipa::ToSchema;
 use uuid::Uuid;
@@ -18,31 +20,54 @@ use uuid::Uuid;
 pub struct LoginInformation {
     username: String,
     password: String,
+    /// Return the session token in the body instead of setting a cookie, for clients that can't
+    /// keep cookies. It is then sent as `Authorization: Bearer <token>`.
+    #[serde(default)]
+    token: bool,
 }
 
-const SESSION_DURATION_MINUTES: i64 = 30;
+/// JSON response after logging in.
+#[derive(Serialize, ToSchema)]
+pub struct LoginResponse {
+    message: &'static str,
+    /// The session token, only when it was asked for instead of a cookie.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+/// The message returned after logging in.
+static LOGIN_SUCCESSFUL: &str = "Login successful.";
+
+/// How long a session stays valid without requests.
+pub(crate) const SESSION_DURATION_MINUTES: i64 = 30;
 const COOKIE_MAX_AGE_SECONDS: i64 = SESSION_DURATION_MINUTES * 60;
 
 /// The login endpoint for the API.
 ///
 /// Upon successful login the API will grant a cookie attached with a UUID which grants access to
-/// the matching account.
+/// the matching account. Clients without cookies can ask for the UUID in the body instead and send
+/// it as a bearer token.
 #[utoipa::path(
     post,
     path = "/login",
     request_body = LoginInformation,
     responses(
-        (status = 200, description = "Login successful.", body = GenericResponse),
+        (status = 200, description = LOGIN_SUCCESSFUL, body = LoginResponse),
         (status = 403, description = WRONG_PASSWORD, body = GenericResponse),
         (status = 500, description = SERVER_ERROR, body = GenericResponse),
     )
 )]
 #[post("/login")]
 pub async fn login_request(
+    req: HttpRequest,
     info: Either<Json<LoginInformation>, Form<LoginInformation>>,
     db: Data<PgPool>,
 ) -> impl Responder {
-    let LoginInformation { username, password } = info.into_inner();
+    let LoginInformation {
+        username,
+        password,
+        token,
+    } = info.into_inner();
 
     let matched_user = sqlx::query_as!(User, "select * from users where user_name = $1", username)
         .fetch_one(db.get_ref())
@@ -71,28 +96,35 @@ pub async fn login_request(
 
             // Generate secure login token
             let login_id = Uuid::new_v4();
-            let now = Utc::now();
-
-            // Update both login_id and last_login in a single transaction
-            let update_result = sqlx::query!(
-                r#"
-                    UPDATE users
-                    SET login_id = $1, user_last_login = $2
-                    WHERE user_id = $3
-                    "#,
-                login_id,
-                now,
-                user.user_id
-            )
-            .execute(db.get_ref())
-            .await;
-
-            if update_result.is_err() {
+
+            // Remembered so the user can tell their sessions apart.
+            let user_agent = req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let ip = req
+                .connection_info()
+                .realip_remote_addr()
+                .map(str::to_string);
+
+            if let Err(e) = start_session(&user, login_id, user_agent, ip, db.get_ref()).await {
+                error!(
+                    "Failed to start a session for user {}: {}",
+                    user.user_name, e
+                );
                 return HttpResponse::InternalServerError().json(GenericResponse {
                     message: DATABASE_ERROR,
                 });
             }
 
+            if token {
+                return HttpResponse::Ok().json(LoginResponse {
+                    message: LOGIN_SUCCESSFUL,
+                    token: Some(login_id.to_string()),
+                });
+            }
+
             let cookie = Cookie::build("login_id", login_id.to_string())
                 .path("/")
                 .max_age(cookie::time::Duration::seconds(COOKIE_MAX_AGE_SECONDS))
@@ -102,8 +134,9 @@ pub async fn login_request(
                 .http_only(true)
                 .finish();
 
-            HttpResponse::Ok().cookie(cookie).json(GenericResponse {
-                message: "Login successful.",
+            HttpResponse::Ok().cookie(cookie).json(LoginResponse {
+                message: LOGIN_SUCCESSFUL,
+                token: None,
             })
         }
         Err(Error::RowNotFound) => {
@@ -119,11 +152,57 @@ pub async fn login_request(
     }
 }
 
+/// Store a new session for a user who just logged in, leaving their other sessions alone.
+async fn start_session(
+    user: &User,
+    login_id: Uuid,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    db: &PgPool,
+) -> Result<(), Error> {
+    let now = Utc::now();
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(
+        r#"
+        insert into sessions (user_id, login_id, created_at, last_seen, user_agent, ip)
+        values ($1, $2, $3, $3, $4, $5)
+        "#,
+        user.user_id,
+        login_id,
+        now,
+        user_agent,
+        ip
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "update users set user_last_login = $1 where user_id = $2",
+        now,
+        user.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Expired sessions are otherwise only removed when they are used again.
+    sqlx::query!(
+        "delete from sessions where user_id = $1 and last_seen < $2",
+        user.user_id,
+        now - Duration::minutes(SESSION_DURATION_MINUTES)
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
 /// Middleware approach to ensuring a user has the credentials to access a certain section of the
 /// API.
 ///
-/// This will take an HttpRequest and a PgPool and confirm the user's session cookie, returning the
-/// user_id or an HttpResponse with some sort of error.
+/// This will take an HttpRequest and a PgPool and confirm the user's session, returning the user
+/// or an HttpResponse with some sort of error. The session is read from an `Authorization: Bearer`
+/// header if there is one, and from the `login_id` cookie otherwise.
 ///
 /// This should be used as a guard to disallow unauthorized users.
 ///
@@ -137,59 +216,135 @@ pub async fn login_request(
 /// }
 /// ```
 pub async fn validate_session(req: &HttpRequest, db: &PgPool) -> Result<User, HttpResponse> {
-    // Retrieve the request's cookie.
-    let cookie = match req.cookie("login_id") {
-        Some(cookie) => cookie,
-        None => {
-            return Err(HttpResponse::Unauthorized().json(GenericResponse {
-                message: "Missing login_id.",
-            }));
+    let login_id = request_login_id(req).map_err(HttpResponse::from)?;
+
+    match session_user(login_id, db).await {
+        Ok(user) => Ok(user),
+        Err(SessionError::Invalid) => Err(HttpResponse::Unauthorized().json(GenericResponse {
+            message: BAD_SESSION,
+        })),
+        Err(SessionError::Database(_)) => {
+            Err(HttpResponse::InternalServerError().json(GenericResponse {
+                message: DATABASE_ERROR,
+            }))
         }
-    };
+    }
+}
 
-    // Convert to UUID form.
-    let login_id = match Uuid::parse_str(&cookie.value().to_string()) {
-        Ok(login_id) => login_id,
-        Err(_) => {
-            return Err(HttpResponse::BadRequest().json(GenericResponse {
+/// Why a request's session ID couldn't be read.
+pub(crate) enum LoginIdError {
+    /// Neither a bearer token nor a cookie was sent.
+    Missing,
+    /// The session ID isn't a UUID.
+    Malformed,
+}
+
+impl From<LoginIdError> for HttpResponse {
+    fn from(e: LoginIdError) -> Self {
+        match e {
+            LoginIdError::Missing => HttpResponse::Unauthorized().json(GenericResponse {
+                message: "Missing login_id.",
+            }),
+            LoginIdError::Malformed => HttpResponse::BadRequest().json(GenericResponse {
                 message: "Invalid login_id.",
-            }));
+            }),
         }
-    };
+    }
+}
 
+/// Read the session ID a request was sent with, without checking it.
+pub(crate) fn request_login_id(req: &HttpRequest) -> Result<Uuid, LoginIdError> {
+    // Retrieve the request's bearer token or cookie.
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let session = bearer
+        .or_else(|| req.cookie("login_id").map(|c| c.value().to_string()))
+        .ok_or(LoginIdError::Missing)?;
+
+    // Convert to UUID form.
+    Uuid::parse_str(session.trim()).map_err(|_| LoginIdError::Malformed)
+}
+
+/// Why a session was refused.
+pub(crate) enum SessionError {
+    /// The session doesn't exist or expired.
+    Invalid,
+    Database(Error),
+}
+
+/// Look up the user a session belongs to, extending the session.
+///
+/// This is the session check shared by cookies, bearer tokens and the gRPC API, without caring
+/// where the session ID came from.
+pub(crate) async fn session_user(login_id: Uuid, db: &PgPool) -> Result<User, SessionError> {
     // Check database.
-    let user = sqlx::query_as!(User, "select * from users where login_id = $1", login_id)
-        .fetch_one(db)
-        .await
-        .map_err(|_| HttpResponse::InternalServerError())?;
+    let session = sqlx::query!(
+        "select session_id, user_id, last_seen from sessions where login_id = $1",
+        login_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(SessionError::Database)?
+    .ok_or(SessionError::Invalid)?;
 
-    // Check when user last logged in.
+    // Check when the session was last used.
     let now = Utc::now();
-    if now.signed_duration_since(user.user_last_login) > Duration::minutes(SESSION_DURATION_MINUTES)
-    {
-        // Set login_id in database to null.
+    if now.signed_duration_since(session.last_seen) > Duration::minutes(SESSION_DURATION_MINUTES) {
         // If this fails (impossible theoretically) then it's fine as this will just run again next
         // attempt to access something.
         let _ = sqlx::query!(
-            "update users set login_id = null where user_id = $1",
-            user.user_id
+            "delete from sessions where session_id = $1",
+            session.session_id
         )
         .execute(db)
         .await;
 
-        return Err(HttpResponse::Unauthorized().json(GenericResponse {
-            message: BAD_SESSION,
-        }));
+        return Err(SessionError::Invalid);
     }
 
-    // Reset user's last login time to now. AKA a "sliding session"
+    // Reset the session's last use to now. AKA a "sliding session"
     let _ = sqlx::query!(
-        "update users set user_last_login = $1 where user_id = $2",
+        "update sessions set last_seen = $1 where session_id = $2",
         now,
-        user.user_id
+        session.session_id
     )
     .execute(db)
     .await;
 
-    Ok(user)
+    sqlx::query_as!(
+        User,
+        "select * from users where user_id = $1",
+        session.user_id
+    )
+    .fetch_one(db)
+    .await
+    .map_err(SessionError::Database)
+}
+
+/// Look up the user a session belongs to without touching the session.
+///
+/// For services checking a session on behalf of a client. Only the client using it should keep a
+/// session alive, and expired sessions are left for `session_user` to clean up.
+pub(crate) async fn peek_session_user(login_id: Uuid, db: &PgPool) -> Result<User, SessionError> {
+    let active_since = Utc::now() - Duration::minutes(SESSION_DURATION_MINUTES);
+
+    sqlx::query_as!(
+        User,
+        r#"
+        select users.* from users
+        join sessions on sessions.user_id = users.user_id
+        where sessions.login_id = $1 and sessions.last_seen >= $2
+        "#,
+        login_id,
+        active_since
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(SessionError::Database)?
+    .ok_or(SessionError::Invalid)
 }
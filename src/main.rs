@@ -1,24 +1,62 @@
-mod agent;
+mod action;
+mod agent_info;
+mod annotation;
+mod answer_cache;
 mod api_messages;
+mod bulk;
+mod compare;
 mod conversation;
+mod document;
 mod documentation;
+mod grpc;
+mod health;
 mod login;
-mod proto;
+mod progress;
 mod register;
+mod request_id;
+mod revision;
+mod session;
+mod template;
 mod user;
 
 use std::error::Error;
+use std::time::Duration;
 
-use crate::agent::CogitoAgent;
-use crate::conversation::{create_conversation, delete_conversation, get_conversation};
+use cogito_api::{agent, answer, context, proto};
+
+use crate::action::{ActionRegistry, get_pending_actions, resolve_action};
+use crate::agent::{AgentConfig, CogitoAgent};
+use crate::agent_info::{agent_capabilities, agent_status};
+use crate::annotation::{create_annotation, delete_annotation, get_annotations, update_annotation};
+use crate::answer_cache::{AnswerCache, answer_cache_stats};
+use crate::bulk::bulk_conversations;
+use crate::compare::{create_comparison, get_comparison, vote_comparison};
+use crate::context::ContextConfig;
+use crate::conversation::{
+    ask_follow_up, create_conversation, delete_conversation, get_conversation, rename_conversation,
+};
+use crate::document::{
+    delete_document, get_conversation_documents, get_document, upload_document_resource,
+};
 use crate::documentation::ApiDoc;
+use crate::grpc::ApiService;
+use crate::health::{healthz, readyz};
 use crate::login::login_request;
+use crate::progress::{ProgressHub, get_progress};
 use crate::register::register_request;
-use crate::user::user_by_id;
+use crate::request_id::{REQUEST_ID_HEADER, RequestId, assign_request_id};
+use crate::revision::{get_revision, get_revisions};
+use crate::session::{delete_session, get_sessions, logout, logout_all};
+use crate::template::{
+    conversation_from_template, create_template, delete_template, get_template, get_templates,
+    update_template,
+};
+use crate::user::{set_answer_cache, user_by_id};
 use actix_cors::Cors;
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
 use actix_web::{App, HttpServer};
-use actix_web::{http::header, middleware::Logger};
+use actix_web::{HttpMessage, http::header, middleware::Logger};
 use dotenvy::dotenv;
 use env_logger::Env;
 use sqlx::PgPool;
@@ -36,10 +74,11 @@ async fn setup_postgres() -> Result<PgPool, Box<dyn Error>> {
 }
 
 /// Setup connection with the Cogito agent.
-async fn setup_cogito_agent() -> Result<CogitoAgent, Box<dyn Error>> {
-    let agent_url = std::env::var("COGITO_AGENT_URL")
-        .expect("Expected `COGITO_AGENT_URL` environment variable.");
-    Ok(CogitoAgent::connect(agent_url).await?)
+///
+/// This doesn't wait for any agent replica to be reachable, the connections are made in the
+/// background so the API can start while the agent is down.
+fn setup_cogito_agent() -> Result<CogitoAgent, Box<dyn Error>> {
+    CogitoAgent::connect_lazy(AgentConfig::from_env()?)
 }
 
 #[actix_web::main]
@@ -56,9 +95,38 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to connect to PostgreSQL server.");
 
-    let cogito_agent = setup_cogito_agent()
-        .await
-        .expect("Failed to connect to the Cogito agent.");
+    let cogito_agent = setup_cogito_agent().expect("Invalid Cogito agent configuration.");
+
+    // Answers are only cached when a TTL is configured.
+    let answer_cache_ttl = match std::env::var("COGITO_ANSWER_CACHE_TTL_SECS") {
+        Ok(ttl) => Some(Duration::from_secs(
+            ttl.parse()
+                .expect("Invalid `COGITO_ANSWER_CACHE_TTL_SECS` environment variable."),
+        )),
+        Err(_) => None,
+    };
+    let answer_cache = Data::new(AnswerCache::new(answer_cache_ttl));
+    let progress_hub = Data::new(ProgressHub::default());
+    let action_registry = Data::new(ActionRegistry::default());
+    let context_config =
+        Data::new(ContextConfig::from_env().expect("Invalid context window configuration."));
+
+    // The gRPC API is only served when asked for, next to the REST API.
+    if let Ok(grpc_url) = std::env::var("COGITO_GRPC_URL") {
+        // An empty token would let anyone read every user's data.
+        let token = std::env::var("COGITO_GRPC_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .expect("Expected a non-empty `COGITO_GRPC_TOKEN` environment variable to serve gRPC.");
+        let listener = tokio::net::TcpListener::bind(&grpc_url).await?;
+        let service = ApiService::new(postgres_pool.clone(), &token);
+
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(listener, service).await {
+                log::error!("The gRPC API stopped: {}", e);
+            }
+        });
+    }
 
     let server_url = std::env::var("COGITO_API_URL").unwrap_or_else(|_| "127.0.0.1:8080".into());
 
@@ -73,21 +141,74 @@ async fn main() -> std::io::Result<()> {
                 header::AUTHORIZATION,
                 header::ACCEPT,
                 header::CONTENT_TYPE,
+                header::HeaderName::from_static(REQUEST_ID_HEADER),
             ])
+            .expose_headers(vec![REQUEST_ID_HEADER])
             .supports_credentials()
             .max_age(3600);
 
+        // Same as the default format, plus the request id to match up with agent logs.
+        let logger =
+            Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{request_id}xi"#)
+                .custom_request_replace("request_id", |req| {
+                    req.extensions()
+                        .get::<RequestId>()
+                        .map(|id| id.0.clone())
+                        .unwrap_or_else(|| "-".to_string())
+                });
+
         App::new()
-            .wrap(Logger::default())
+            .wrap(logger)
             .wrap(cors)
+            // Outermost so the id exists before anything else sees the request.
+            .wrap(from_fn(assign_request_id))
             .app_data(Data::new(postgres_pool.clone()))
-            .app_data(cogito_agent.clone())
+            .app_data(Data::new(cogito_agent.clone()))
+            .app_data(answer_cache.clone())
+            .app_data(progress_hub.clone())
+            .app_data(action_registry.clone())
+            .app_data(context_config.clone())
+            .service(healthz)
+            .service(readyz)
+            .service(agent_status)
+            .service(agent_capabilities)
+            .service(answer_cache_stats)
             .service(user_by_id)
+            .service(set_answer_cache)
             .service(login_request)
+            .service(logout)
+            .service(logout_all)
+            .service(get_sessions)
+            .service(delete_session)
             .service(register_request)
             .service(create_conversation)
             .service(get_conversation)
             .service(delete_conversation)
+            .service(rename_conversation)
+            .service(ask_follow_up)
+            .service(bulk_conversations)
+            .service(get_revisions)
+            .service(get_revision)
+            .service(get_templates)
+            .service(create_template)
+            .service(get_template)
+            .service(update_template)
+            .service(delete_template)
+            .service(conversation_from_template)
+            .service(get_progress)
+            .service(get_pending_actions)
+            .service(resolve_action)
+            .service(create_comparison)
+            .service(get_comparison)
+            .service(vote_comparison)
+            .service(upload_document_resource())
+            .service(get_document)
+            .service(delete_document)
+            .service(get_conversation_documents)
+            .service(get_annotations)
+            .service(create_annotation)
+            .service(update_annotation)
+            .service(delete_annotation)
             .service(Redoc::with_url("/redoc", ApiDoc::openapi()))
     })
     .bind(server_url)?
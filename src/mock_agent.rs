@@ -0,0 +1,478 @@
+//! A stand-in for the Cogito agent.
+//!
+//! The real agent is a Python service that needs model access, so it can't run in CI or on every
+//! developer's machine. This mock speaks the same gRPC protocol and answers from a script, which
+//! lets the API be developed and integration tested fully offline.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::answer::{ANSWER_PROTOCOL_VERSION, AgentConversation, AgentMessage, Citation, Role};
+use crate::proto::cogito_server::{Cogito, CogitoServer};
+use crate::proto::{
+    ActionDecision, ActionDecisionAck, Answer, ApprovalRequest, AskEvent, Capabilities,
+    CapabilitiesRequest, Corpus, Model, OptionRange, Progress, Question, Summary, SummaryRequest,
+    Tool, ask_event,
+};
+use rand::Rng;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::transport::Server;
+use tonic::transport::server::Router;
+use tonic::{Code, Request, Response, Status};
+use uuid::Uuid;
+
+/// A scripted reply to the next question.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MockResponse {
+    /// Answer with this text, citing the configured citations.
+    Answer(String),
+    /// Send this answer as is, e.g. to test how malformed answers are handled.
+    Raw {
+        content: String,
+        protocol_version: u32,
+    },
+    /// Fail with a gRPC status code, see `tonic::Code` for the numbers.
+    Error { code: i32, message: String },
+}
+
+/// How the mock agent behaves.
+///
+/// This can be loaded from JSON, where every field is optional.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MockConfig {
+    /// How long every answer takes.
+    pub latency_ms: u64,
+    /// Chance between 0 and 1 that a question fails with `Unavailable`.
+    pub error_rate: f64,
+    /// Sources cited by every answer.
+    pub citations: Vec<Citation>,
+    /// Steps reported by `AskWithProgress` before the answer.
+    pub progress: Vec<String>,
+    /// Actions `AskWithProgress` asks approval for after the steps, by description.
+    pub approvals: Vec<String>,
+    /// How long the mock waits for each decision before treating the action as denied.
+    pub approval_timeout_secs: u32,
+    /// Replies to the next questions, in order. Once this runs out questions are echoed back.
+    pub script: Vec<MockResponse>,
+    /// Shared secret the API must send, like the real agent. Anyone may ask if this is missing.
+    pub token: Option<String>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            latency_ms: 0,
+            error_rate: 0.0,
+            citations: fixture_citations(),
+            progress: vec![
+                "Searching the Stanford Encyclopedia of Philosophy".to_string(),
+                "Reading Kant, Groundwork §2".to_string(),
+                "Drafting answer".to_string(),
+            ],
+            approvals: Vec::new(),
+            approval_timeout_secs: 30,
+            script: Vec::new(),
+            token: None,
+        }
+    }
+}
+
+/// Canned citations for answers that don't care about their sources.
+pub fn fixture_citations() -> Vec<Citation> {
+    vec![
+        Citation {
+            source: "Stanford Encyclopedia of Philosophy".to_string(),
+            title: Some("Kant's Moral Philosophy".to_string()),
+            locator: Some("§2".to_string()),
+            url: Some("https://plato.stanford.edu/entries/kant-moral/".to_string()),
+        },
+        Citation {
+            source: "Immanuel Kant".to_string(),
+            title: Some("Groundwork of the Metaphysics of Morals".to_string()),
+            locator: Some("4:421".to_string()),
+            url: None,
+        },
+    ]
+}
+
+/// Capabilities reported by the mock, resembling those of the real agent.
+pub fn fixture_capabilities() -> Capabilities {
+    Capabilities {
+        agent_version: format!("mock-{}", env!("CARGO_PKG_VERSION")),
+        models: vec![
+            Model {
+                id: "mock-deep".to_string(),
+                name: "Deep research".to_string(),
+                description: "Reads several sources before answering.".to_string(),
+                is_default: true,
+            },
+            Model {
+                id: "mock-quick".to_string(),
+                name: "Quick answer".to_string(),
+                description: "Answers from a single source.".to_string(),
+                is_default: false,
+            },
+        ],
+        corpora: vec![Corpus {
+            id: "sep".to_string(),
+            name: "Stanford Encyclopedia of Philosophy".to_string(),
+            description: "Peer reviewed encyclopedia entries.".to_string(),
+        }],
+        tools: vec![Tool {
+            id: "web_search".to_string(),
+            name: "Web search".to_string(),
+            description: "Searches the web for sources outside the corpora.".to_string(),
+        }],
+        options: vec![OptionRange {
+            name: "max_sources".to_string(),
+            description: "How many sources to read at most.".to_string(),
+            min: 1.0,
+            max: 20.0,
+            default_value: 5.0,
+            integer: true,
+        }],
+    }
+}
+
+/// Mock implementation of the Cogito agent's gRPC service.
+///
+/// Cloning this is cheap and every clone shares the same script, so a test can keep a clone to
+/// script replies and inspect questions while the server runs.
+#[derive(Clone)]
+pub struct MockAgent {
+    inner: Arc<MockState>,
+}
+
+struct MockState {
+    latency: Duration,
+    error_rate: f64,
+    citations: Vec<Citation>,
+    progress: Vec<String>,
+    approvals: Vec<String>,
+    approval_timeout: Duration,
+    token: Option<String>,
+    script: Mutex<VecDeque<MockResponse>>,
+    questions: Mutex<Vec<Question>>,
+    /// Streams waiting for a decision, by action ID.
+    pending_actions: Mutex<HashMap<String, oneshot::Sender<ActionDecision>>>,
+    decisions: Mutex<Vec<ActionDecision>>,
+}
+
+impl MockAgent {
+    pub fn new(config: MockConfig) -> Self {
+        MockAgent {
+            inner: Arc::new(MockState {
+                latency: Duration::from_millis(config.latency_ms),
+                error_rate: config.error_rate.clamp(0.0, 1.0),
+                citations: config.citations,
+                progress: config.progress,
+                approvals: config.approvals,
+                approval_timeout: Duration::from_secs(config.approval_timeout_secs.into()),
+                token: config.token,
+                script: Mutex::new(config.script.into()),
+                questions: Mutex::new(Vec::new()),
+                pending_actions: Mutex::new(HashMap::new()),
+                decisions: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Queue a reply after those already scripted.
+    pub fn push_response(&self, response: MockResponse) {
+        self.inner.script.lock().unwrap().push_back(response);
+    }
+
+    /// Every question received so far.
+    pub fn questions(&self) -> Vec<Question> {
+        self.inner.questions.lock().unwrap().clone()
+    }
+
+    /// Every decision on an action received so far.
+    pub fn decisions(&self) -> Vec<ActionDecision> {
+        self.inner.decisions.lock().unwrap().clone()
+    }
+
+    /// The mock's services, including a `grpc.health.v1` service that always reports serving.
+    async fn router(self) -> Router {
+        let (reporter, health_service) = tonic_health::server::health_reporter();
+        reporter.set_serving::<CogitoServer<MockAgent>>().await;
+
+        Server::builder()
+            .add_service(health_service)
+            .add_service(CogitoServer::new(self))
+    }
+
+    /// Serve the mock agent on an address until the process exits.
+    pub async fn serve(self, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+        self.router().await.serve(address).await
+    }
+
+    /// Serve the mock agent on an already bound listener.
+    ///
+    /// Binding to port 0 first lets tests run in parallel without fighting over ports.
+    pub async fn serve_with_listener(
+        self,
+        listener: TcpListener,
+    ) -> Result<(), tonic::transport::Error> {
+        self.router()
+            .await
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+
+    /// Reject calls that don't carry the configured token.
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(token) = &self.inner.token else {
+            return Ok(());
+        };
+
+        let expected = format!("Bearer {}", token);
+        match request.metadata().get("authorization") {
+            Some(value) if value.to_str().is_ok_and(|value| value == expected) => Ok(()),
+            _ => Err(Status::unauthenticated("Missing or wrong API token.")),
+        }
+    }
+
+    /// Decide how to reply to a question.
+    fn reply(&self, question: &Question) -> Result<Answer, Status> {
+        self.inner.questions.lock().unwrap().push(question.clone());
+
+        // Injected failures don't use up the script.
+        if rand::thread_rng().gen_bool(self.inner.error_rate) {
+            return Err(Status::unavailable("Injected mock agent failure."));
+        }
+
+        let scripted = self.inner.script.lock().unwrap().pop_front();
+        match scripted {
+            Some(MockResponse::Answer(text)) => Ok(self.answer(question, text)),
+            Some(MockResponse::Raw {
+                content,
+                protocol_version,
+            }) => Ok(Answer {
+                content,
+                protocol_version,
+                ..self.provenance(question)
+            }),
+            Some(MockResponse::Error { code, message }) => {
+                Err(Status::new(Code::from(code), message))
+            }
+            None => Ok(self.answer(
+                question,
+                format!("This is a mock answer to \"{}\".", question.content),
+            )),
+        }
+    }
+
+    /// Build a valid answer continuing from the question.
+    fn answer(&self, question: &Question, text: String) -> Answer {
+        let conversation = AgentConversation {
+            messages: vec![
+                AgentMessage {
+                    role: Role::User,
+                    content: question.content.clone(),
+                    citations: Vec::new(),
+                    trace: Vec::new(),
+                    provenance: None,
+                    context: None,
+                },
+                AgentMessage {
+                    role: Role::Assistant,
+                    content: text,
+                    citations: self.inner.citations.clone(),
+                    trace: Vec::new(),
+                    provenance: None,
+                    context: None,
+                },
+            ],
+        };
+
+        Answer {
+            content: serde_json::to_string(&conversation)
+                .expect("Mock conversations always serialize."),
+            protocol_version: ANSWER_PROTOCOL_VERSION,
+            ..self.provenance(question)
+        }
+    }
+
+    /// An empty answer reporting the mock's version and the model the question asked for.
+    fn provenance(&self, question: &Question) -> Answer {
+        let capabilities = fixture_capabilities();
+        let model = question
+            .options
+            .as_ref()
+            .map(|options| options.model.clone())
+            .filter(|model| !model.is_empty())
+            .or_else(|| {
+                capabilities
+                    .models
+                    .into_iter()
+                    .find(|model| model.is_default)
+                    .map(|model| model.id)
+            })
+            .unwrap_or_default();
+
+        Answer {
+            agent_version: capabilities.agent_version,
+            model,
+            ..Answer::default()
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Cogito for MockAgent {
+    async fn ask(&self, request: Request<Question>) -> Result<Response<Answer>, Status> {
+        self.authenticate(&request)?;
+        let question = request.into_inner();
+        tokio::time::sleep(self.inner.latency).await;
+
+        self.reply(&question).map(Response::new)
+    }
+
+    type AskWithProgressStream = ReceiverStream<Result<AskEvent, Status>>;
+
+    async fn ask_with_progress(
+        &self,
+        request: Request<Question>,
+    ) -> Result<Response<Self::AskWithProgressStream>, Status> {
+        self.authenticate(&request)?;
+        let question = request.into_inner();
+        let agent = self.clone();
+        let (sender, receiver) = mpsc::channel(4);
+
+        tokio::spawn(async move {
+            // Spread the latency over the steps so progress trickles in like it would for real.
+            let step = agent.inner.latency / (agent.inner.progress.len() as u32 + 1);
+
+            for description in &agent.inner.progress {
+                tokio::time::sleep(step).await;
+                let event = AskEvent {
+                    event: Some(ask_event::Event::Progress(Progress {
+                        description: description.clone(),
+                        ..Progress::default()
+                    })),
+                };
+                if sender.send(Ok(event)).await.is_err() {
+                    // The client hung up.
+                    return;
+                }
+            }
+
+            for description in &agent.inner.approvals {
+                let (decided, decision) = oneshot::channel();
+                let action_id = Uuid::new_v4().to_string();
+                agent
+                    .inner
+                    .pending_actions
+                    .lock()
+                    .unwrap()
+                    .insert(action_id.clone(), decided);
+
+                let event = AskEvent {
+                    event: Some(ask_event::Event::ApprovalRequest(ApprovalRequest {
+                        action_id: action_id.clone(),
+                        kind: "fetch_source".to_string(),
+                        description: description.clone(),
+                        timeout_secs: agent.inner.approval_timeout.as_secs() as u32,
+                    })),
+                };
+                if sender.send(Ok(event)).await.is_err() {
+                    agent
+                        .inner
+                        .pending_actions
+                        .lock()
+                        .unwrap()
+                        .remove(&action_id);
+                    return;
+                }
+
+                let approved = tokio::time::timeout(agent.inner.approval_timeout, decision)
+                    .await
+                    .is_ok_and(|decision| decision.is_ok_and(|decision| decision.approved));
+                agent
+                    .inner
+                    .pending_actions
+                    .lock()
+                    .unwrap()
+                    .remove(&action_id);
+
+                let outcome = if approved { "Approved" } else { "Skipped" };
+                let event = AskEvent {
+                    event: Some(ask_event::Event::Progress(Progress {
+                        description: format!("{}: {}", outcome, description),
+                        ..Progress::default()
+                    })),
+                };
+                if sender.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(step).await;
+            let event = agent.reply(&question).map(|answer| AskEvent {
+                event: Some(ask_event::Event::Answer(answer)),
+            });
+            let _ = sender.send(event).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get_capabilities(
+        &self,
+        request: Request<CapabilitiesRequest>,
+    ) -> Result<Response<Capabilities>, Status> {
+        self.authenticate(&request)?;
+        Ok(Response::new(fixture_capabilities()))
+    }
+
+    async fn summarize(
+        &self,
+        request: Request<SummaryRequest>,
+    ) -> Result<Response<Summary>, Status> {
+        self.authenticate(&request)?;
+        let request = request.into_inner();
+
+        // Keeping the start of every message is a crude summary, but a short and predictable one.
+        let mut content = request.previous_summary;
+        for message in &request.messages {
+            let start: String = message.content.chars().take(40).collect();
+            content.push_str(&format!("{} said: {}... ", message.role, start));
+        }
+
+        Ok(Response::new(Summary {
+            content: content.trim_end().to_string(),
+        }))
+    }
+
+    async fn resolve_action(
+        &self,
+        request: Request<ActionDecision>,
+    ) -> Result<Response<ActionDecisionAck>, Status> {
+        self.authenticate(&request)?;
+        let decision = request.into_inner();
+
+        let waiting = self
+            .inner
+            .pending_actions
+            .lock()
+            .unwrap()
+            .remove(&decision.action_id);
+        let Some(waiting) = waiting else {
+            return Err(Status::not_found("No stream is waiting for this action."));
+        };
+
+        self.inner.decisions.lock().unwrap().push(decision.clone());
+        // The stream may have timed out in the meantime, which is fine.
+        let _ = waiting.send(decision);
+
+        Ok(Response::new(ActionDecisionAck {}))
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::action::PendingActionInfo;
+use crate::answer::ResearchStep;
+use crate::api_messages::{BAD_SESSION, GenericResponse};
+use crate::login::validate_session;
+use actix_web::web::{Bytes, Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder, get};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// Deep research takes minutes and the request creating a conversation only returns at the end.
+// To show what is going on, the client picks a progress ID, subscribes to `/progress/{id}` and
+// passes the same ID when creating the conversation. It doesn't matter which happens first.
+
+/// How long finished progress is kept for late subscribers.
+const FINISHED_RETENTION: Duration = Duration::from_secs(60);
+
+/// How long a subscription waits for its conversation to start before it is dropped.
+const PENDING_RETENTION: Duration = Duration::from_secs(600);
+
+/// An event on a progress stream.
+#[derive(Serialize, ToSchema, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// The agent took a research step.
+    Step(ResearchStep),
+    /// The agent waits for the user to approve an action at `/actions/{action_id}`.
+    ApprovalNeeded(PendingActionInfo),
+    /// The conversation was created, always the last event on success.
+    Done { conversation_id: i32 },
+    /// Creating the conversation failed, always the last event on failure.
+    Failed,
+}
+
+impl ProgressEvent {
+    /// Format the event for a `text/event-stream` response.
+    fn to_sse(&self) -> Bytes {
+        let name = match self {
+            ProgressEvent::Step(_) => "step",
+            ProgressEvent::ApprovalNeeded(_) => "approval_needed",
+            ProgressEvent::Done { .. } => "done",
+            ProgressEvent::Failed => "failed",
+        };
+        // Serializing these can't fail.
+        let data = serde_json::to_string(self).unwrap_or_default();
+
+        Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+    }
+}
+
+/// Progress of one conversation being created.
+struct ProgressChannel {
+    user_id: i32,
+    /// Every event so far, replayed to new subscribers.
+    history: Vec<ProgressEvent>,
+    subscribers: Vec<UnboundedSender<ProgressEvent>>,
+    created_at: Instant,
+    finished_at: Option<Instant>,
+}
+
+impl ProgressChannel {
+    fn new(user_id: i32) -> Self {
+        ProgressChannel {
+            user_id,
+            history: Vec::new(),
+            subscribers: Vec::new(),
+            created_at: Instant::now(),
+            finished_at: None,
+        }
+    }
+
+    fn publish(&mut self, event: ProgressEvent) {
+        // Subscribers that went away are dropped.
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        self.history.push(event);
+    }
+
+    fn is_stale(&mut self, now: Instant) -> bool {
+        // Clients that disconnected would otherwise keep the channel alive until the next event.
+        self.subscribers.retain(|subscriber| !subscriber.is_closed());
+
+        match self.finished_at {
+            Some(finished_at) => now.duration_since(finished_at) > FINISHED_RETENTION,
+            None => {
+                self.subscribers.is_empty()
+                    && now.duration_since(self.created_at) > PENDING_RETENTION
+            }
+        }
+    }
+}
+
+/// Relays research progress from conversations being created to their subscribers.
+///
+/// Progress only lives in memory, the final trace is stored with the answer.
+#[derive(Default)]
+pub struct ProgressHub {
+    channels: Mutex<HashMap<Uuid, ProgressChannel>>,
+}
+
+impl ProgressHub {
+    /// Get the channel for an ID, creating it for `user_id` if needed.
+    ///
+    /// Returns `None` if the ID belongs to another user.
+    fn channel(
+        channels: &mut HashMap<Uuid, ProgressChannel>,
+        progress_id: Uuid,
+        user_id: i32,
+    ) -> Option<&mut ProgressChannel> {
+        let now = Instant::now();
+        channels.retain(|_, channel| !channel.is_stale(now));
+
+        let channel = channels
+            .entry(progress_id)
+            .or_insert_with(|| ProgressChannel::new(user_id));
+
+        (channel.user_id == user_id).then_some(channel)
+    }
+
+    /// Start reporting progress for a conversation being created.
+    ///
+    /// Without a progress ID, or with one belonging to someone else, the trace is still collected
+    /// but nothing is relayed.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        progress_id: Option<Uuid>,
+        user_id: i32,
+    ) -> ProgressReporter {
+        let progress_id = progress_id.filter(|progress_id| {
+            let mut channels = self.channels.lock().unwrap();
+            match ProgressHub::channel(&mut channels, *progress_id, user_id) {
+                Some(channel) => {
+                    // A reused ID starts over.
+                    if channel.finished_at.is_some() {
+                        *channel = ProgressChannel::new(user_id);
+                    }
+                    true
+                }
+                None => false,
+            }
+        });
+
+        ProgressReporter {
+            hub: Arc::clone(self),
+            progress_id,
+            trace: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Subscribe to the progress of a conversation, replaying what happened so far.
+    fn subscribe(
+        &self,
+        progress_id: Uuid,
+        user_id: i32,
+    ) -> Option<UnboundedReceiverStream<ProgressEvent>> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = ProgressHub::channel(&mut channels, progress_id, user_id)?;
+
+        let (sender, receiver) = unbounded_channel();
+        for event in &channel.history {
+            let _ = sender.send(event.clone());
+        }
+        // Finished streams end after the replay since the sender is dropped.
+        if channel.finished_at.is_none() {
+            channel.subscribers.push(sender);
+        }
+
+        Some(UnboundedReceiverStream::new(receiver))
+    }
+
+    fn publish(&self, progress_id: Uuid, event: ProgressEvent, finished: bool) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get_mut(&progress_id) {
+            channel.publish(event);
+            if finished {
+                channel.finished_at = Some(Instant::now());
+                // Closes every subscriber's stream.
+                channel.subscribers.clear();
+            }
+        }
+    }
+}
+
+/// Collects the research trace of a conversation being created and relays it to subscribers.
+///
+/// If this is dropped without finishing, subscribers are told creating the conversation failed.
+pub(crate) struct ProgressReporter {
+    hub: Arc<ProgressHub>,
+    progress_id: Option<Uuid>,
+    trace: Vec<ResearchStep>,
+    finished: bool,
+}
+
+impl ProgressReporter {
+    pub(crate) fn step(&mut self, step: ResearchStep) {
+        if let Some(progress_id) = self.progress_id {
+            self.hub
+                .publish(progress_id, ProgressEvent::Step(step.clone()), false);
+        }
+        self.trace.push(step);
+    }
+
+    /// Announce an action waiting for the user's approval.
+    pub(crate) fn approval_needed(&mut self, action: PendingActionInfo) {
+        if let Some(progress_id) = self.progress_id {
+            self.hub
+                .publish(progress_id, ProgressEvent::ApprovalNeeded(action), false);
+        }
+    }
+
+    /// The steps reported so far.
+    pub(crate) fn trace(&self) -> Vec<ResearchStep> {
+        self.trace.clone()
+    }
+
+    /// Tell subscribers the conversation was created.
+    pub(crate) fn done(mut self, conversation_id: i32) {
+        if let Some(progress_id) = self.progress_id {
+            self.hub
+                .publish(progress_id, ProgressEvent::Done { conversation_id }, true);
+        }
+        self.finished = true;
+    }
+}
+
+impl Drop for ProgressReporter {
+    fn drop(&mut self) {
+        if !self.finished
+            && let Some(progress_id) = self.progress_id
+        {
+            self.hub.publish(progress_id, ProgressEvent::Failed, true);
+        }
+    }
+}
+
+/// Follow the research progress of a conversation being created.
+///
+/// This is a `text/event-stream` of `step` and `approval_needed` events, ending with a `done` or
+/// `failed` event. Each event's data is a JSON `ProgressEvent`. Subscribe with a new UUID and pass
+/// the same UUID as `progress_id` when creating the conversation.
+#[utoipa::path(
+    get,
+    path = "/progress/{progress_id}",
+    params(
+        ("progress_id" = String, Path, description = "The progress UUID chosen by the client.")
+    ),
+    responses(
+        (status = 200, description = "Stream of progress events.", body = ProgressEvent, content_type = "text/event-stream"),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Progress not found.", body = GenericResponse),
+    )
+)]
+#[get("/progress/{progress_id}")]
+pub async fn get_progress(
+    progress_id: Path<Uuid>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+    hub: Data<ProgressHub>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    // Someone else's progress looks the same as none at all.
+    let Some(events) = hub.subscribe(*progress_id, user.user_id) else {
+        return HttpResponse::NotFound().json(GenericResponse {
+            message: "Progress not found.",
+        });
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events.map(|event| Ok::<_, actix_web::Error>(event.to_sse())))
+}
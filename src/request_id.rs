@@ -0,0 +1,64 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpRequest};
+use uuid::Uuid;
+
+/// Header carrying the correlation id of a request, both from clients and to the agent.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Correlation id of an HTTP request.
+///
+/// It is logged with the request, echoed back in the response and sent along with every agent call
+/// the request makes, so logs can be matched up between the API and the agent.
+#[derive(Clone)]
+pub struct RequestId(pub(crate) String);
+
+impl RequestId {
+    /// The id assigned to a request by `assign_request_id`.
+    pub fn of(req: &HttpRequest) -> Self {
+        req.extensions()
+            .get::<RequestId>()
+            .cloned()
+            // Only happens if the middleware isn't installed.
+            .unwrap_or_else(|| RequestId(Uuid::new_v4().to_string()))
+    }
+}
+
+/// Whether a client supplied id is safe to log and forward.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Middleware giving every request a `RequestId`.
+///
+/// A client (or proxy) may pick the id through the `x-request-id` header, otherwise a new one is
+/// generated.
+pub async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let mut response = next.call(req).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+
+    Ok(response)
+}
@@ -0,0 +1,265 @@
+use crate::api_messages::{BAD_SESSION, FORBIDDEN, GenericResponse, SERVER_ERROR};
+use crate::compare::{hide_provenance, is_unvoted_variant};
+use crate::conversation::fetch_conversation;
+use crate::login::validate_session;
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder, get};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::Serialize;
+use sqlx::{Error, PgConnection, PgPool};
+use utoipa::ToSchema;
+
+// Every change to a conversation writes a full snapshot of it to `conversation_revisions`. The log
+// is append-only, so any earlier state of a conversation can be viewed or restored from it. Deleting
+// a conversation records a last `deleted` revision and keeps the log for auditing, although it can
+// no longer be read through the API.
+
+/// The kind of change that produced a revision.
+#[derive(Clone, Copy)]
+pub(crate) enum RevisionKind {
+    /// The conversation was created.
+    Created,
+    /// The conversation title was changed.
+    Renamed,
+    /// A follow-up question was answered.
+    FollowedUp,
+    /// The conversation was deleted, this is its state right before.
+    Deleted,
+    /// The conversation was archived.
+    Archived,
+    /// The conversation was taken out of the archive.
+    Unarchived,
+    /// The conversation was moved to another folder, or out of any folder.
+    Moved,
+    /// A tag was added to the conversation.
+    Tagged,
+}
+
+impl RevisionKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            RevisionKind::Created => "created",
+            RevisionKind::Renamed => "renamed",
+            RevisionKind::FollowedUp => "followed_up",
+            RevisionKind::Deleted => "deleted",
+            RevisionKind::Archived => "archived",
+            RevisionKind::Unarchived => "unarchived",
+            RevisionKind::Moved => "moved",
+            RevisionKind::Tagged => "tagged",
+        }
+    }
+}
+
+/// A single entry in a conversation's revision log, without the conversation contents.
+#[derive(Serialize, ToSchema)]
+pub struct RevisionSummary {
+    revision_number: i32,
+    /// What kind of change produced this revision.
+    revision_kind: String,
+    conversation_title: String,
+    #[schema(value_type = String, format = "date-time")]
+    created_at: DateTime<Utc>,
+}
+
+/// A conversation as it was at a specific revision.
+#[derive(Serialize, ToSchema)]
+pub struct ConversationRevision {
+    conversation_id: i32,
+    revision_number: i32,
+    /// What kind of change produced this revision.
+    revision_kind: String,
+    conversation: serde_json::Value,
+    conversation_title: String,
+    archived: bool,
+    folder: Option<String>,
+    tags: Vec<String>,
+    #[schema(value_type = String, format = "date-time")]
+    created_at: DateTime<Utc>,
+}
+
+/// Append the current state of a conversation to its revision log.
+///
+/// This should be called inside the same transaction as the change itself, after the change has
+/// been written.
+pub(crate) async fn record_revision(
+    conn: &mut PgConnection,
+    conversation_id: i32,
+    kind: RevisionKind,
+) -> Result<(), Error> {
+    // Locked until the transaction ends, so concurrent changes can't pick the same number.
+    sqlx::query!(
+        "select conversation_id from conversations where conversation_id = $1 for update",
+        conversation_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        insert into conversation_revisions (
+            conversation_id, user_id, revision_number, revision_kind, conversation,
+            conversation_title, archived, folder, tags
+        )
+        select
+            conversation_id,
+            user_id,
+            coalesce(
+                (select max(revision_number) from conversation_revisions where conversation_id = $1),
+                0
+            ) + 1,
+            $2,
+            conversation,
+            conversation_title,
+            archived,
+            folder,
+            tags
+        from conversations
+        where conversation_id = $1
+        "#,
+        conversation_id,
+        kind.as_str()
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// List the revision log of a conversation, oldest first.
+#[utoipa::path(
+    get,
+    path = "/conversation/{conversation_id}/revisions",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the conversation.")
+    ),
+    responses(
+        (status = 200, description = "Revisions retrieved successfully.", body = Vec<RevisionSummary>),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Conversation not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+    ))]
+#[get("/conversation/{conversation_id}/revisions")]
+pub async fn get_revisions(
+    conversation_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let conversation = match fetch_conversation(*conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    match sqlx::query_as!(
+        RevisionSummary,
+        r#"
+        select revision_number, revision_kind, conversation_title, created_at
+        from conversation_revisions
+        where conversation_id = $1
+        order by revision_number
+        "#,
+        conversation.conversation_id
+    )
+    .fetch_all(db.get_ref())
+    .await
+    {
+        Ok(revisions) => HttpResponse::Ok().json(revisions),
+        Err(e) => {
+            error!(
+                "Failed to retrieve revisions of conversation {} for user {}: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// View a conversation as it was at a given revision.
+#[utoipa::path(
+    get,
+    path = "/conversation/{conversation_id}/revisions/{revision_number}",
+    params(
+        ("conversation_id" = i32, Path, description = "The ID of the conversation."),
+        ("revision_number" = i32, Path, description = "The revision to view the conversation at.")
+    ),
+    responses(
+        (status = 200, description = "Revision retrieved successfully.", body = ConversationRevision),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = "Conversation or revision not found.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+    ))]
+#[get("/conversation/{conversation_id}/revisions/{revision_number}")]
+pub async fn get_revision(
+    path: Path<(i32, i32)>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let (conversation_id, revision_number) = path.into_inner();
+
+    let conversation = match fetch_conversation(conversation_id, &user, db.get_ref()).await {
+        Ok(convo) => convo,
+        Err(e) => return e,
+    };
+
+    // Which variant answered stays hidden until the comparison is voted on.
+    let hidden = match is_unvoted_variant(conversation.conversation_id, db.get_ref()).await {
+        Ok(hidden) => hidden,
+        Err(e) => {
+            error!(
+                "Failed to check comparisons of conversation {} for user {}: {}",
+                conversation.conversation_id, user.user_name, e
+            );
+            return HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            });
+        }
+    };
+
+    match sqlx::query_as!(
+        ConversationRevision,
+        r#"
+        select conversation_id, revision_number, revision_kind, conversation,
+               conversation_title, archived, folder, tags, created_at
+        from conversation_revisions
+        where conversation_id = $1 and revision_number = $2
+        "#,
+        conversation.conversation_id,
+        revision_number
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(mut revision) => {
+            if hidden {
+                hide_provenance(&mut revision.conversation);
+            }
+            HttpResponse::Ok().json(revision)
+        }
+        Err(Error::RowNotFound) => HttpResponse::NotFound().json(GenericResponse {
+            message: "Revision not found.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to retrieve revision {} of conversation {} for user {}: {}",
+                revision_number, conversation.conversation_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::api_messages::{BAD_SESSION, GenericResponse, SERVER_ERROR};
+use crate::login::{SESSION_DURATION_MINUTES, request_login_id, validate_session};
+use actix_web::cookie::Cookie;
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+use serde::Serialize;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+// Every login is its own session, so users can see where they are logged in and end sessions they
+// no longer trust, e.g. after forgetting to log out on a shared lab computer. Sessions are checked
+// against the database on every request, so ending one takes effect immediately.
+
+/// The message returned when a session doesn't exist or belongs to someone else.
+static SESSION_NOT_FOUND: &str = "Session not found.";
+
+/// A session of the user, without its secret.
+#[derive(Serialize, ToSchema)]
+pub struct Session {
+    session_id: i32,
+    #[schema(value_type = String, format = "date-time")]
+    created_at: DateTime<Utc>,
+    /// When the session was last used.
+    #[schema(value_type = String, format = "date-time")]
+    last_seen: DateTime<Utc>,
+    /// The device's user agent, if it sent one.
+    user_agent: Option<String>,
+    ip: Option<String>,
+    /// Whether this is the session the request was sent with.
+    current: bool,
+}
+
+/// A response that also removes the session cookie, if the client has one.
+fn clear_cookie(message: &'static str) -> HttpResponse {
+    let mut cookie = Cookie::build("login_id", "").path("/").finish();
+    cookie.make_removal();
+
+    HttpResponse::Ok()
+        .cookie(cookie)
+        .json(GenericResponse { message })
+}
+
+/// End the session the request was sent with.
+///
+/// This succeeds even if the session already expired, so clients can always clean up.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    responses(
+        (status = 200, description = "Logged out successfully.", body = GenericResponse),
+        (status = 401, description = "Missing login_id.", body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[post("/logout")]
+pub async fn logout(req: HttpRequest, db: Data<PgPool>) -> impl Responder {
+    let login_id = match request_login_id(&req) {
+        Ok(login_id) => login_id,
+        Err(e) => return e.into(),
+    };
+
+    match sqlx::query!("delete from sessions where login_id = $1", login_id)
+        .execute(db.get_ref())
+        .await
+    {
+        Ok(_) => clear_cookie("Logged out successfully."),
+        Err(e) => {
+            error!("Failed to end a session: {}", e);
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// End every session of the user, including the one the request was sent with.
+#[utoipa::path(
+    post,
+    path = "/logout_all",
+    responses(
+        (status = 200, description = "Logged out everywhere successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[post("/logout_all")]
+pub async fn logout_all(req: HttpRequest, db: Data<PgPool>) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match sqlx::query!("delete from sessions where user_id = $1", user.user_id)
+        .execute(db.get_ref())
+        .await
+    {
+        Ok(_) => clear_cookie("Logged out everywhere successfully."),
+        Err(e) => {
+            error!(
+                "Failed to end the sessions of user {}: {}",
+                user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Get the user's active sessions, most recently used first.
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    responses(
+        (status = 200, description = "Sessions retrieved successfully.", body = Vec<Session>),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[get("/sessions")]
+pub async fn get_sessions(req: HttpRequest, db: Data<PgPool>) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+    // Can't fail, the session was just validated.
+    let login_id = match request_login_id(&req) {
+        Ok(login_id) => login_id,
+        Err(e) => return e.into(),
+    };
+
+    // Expired sessions are only deleted once they are used again or the user logs in.
+    let active_since = Utc::now() - Duration::minutes(SESSION_DURATION_MINUTES);
+
+    match sqlx::query_as!(
+        Session,
+        r#"
+        select session_id, created_at, last_seen, user_agent, ip, login_id = $2 as "current!"
+        from sessions
+        where user_id = $1 and last_seen >= $3
+        order by last_seen desc
+        "#,
+        user.user_id,
+        login_id,
+        active_since
+    )
+    .fetch_all(db.get_ref())
+    .await
+    {
+        Ok(sessions) => HttpResponse::Ok().json(sessions),
+        Err(e) => {
+            error!(
+                "Failed to retrieve sessions for user {}: {}",
+                user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// End one of the user's sessions, e.g. on a device they no longer have.
+#[utoipa::path(
+    delete,
+    path = "/sessions/{session_id}",
+    params(
+        ("session_id" = i32, Path, description = "The ID of the session to end.")
+    ),
+    responses(
+        (status = 200, description = "Session ended successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = SESSION_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[delete("/sessions/{session_id}")]
+pub async fn delete_session(
+    session_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match sqlx::query!(
+        "delete from sessions where session_id = $1 and user_id = $2",
+        *session_id,
+        user.user_id
+    )
+    .execute(db.get_ref())
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::NotFound().json(GenericResponse {
+                message: SESSION_NOT_FOUND,
+            })
+        }
+        Ok(_) => HttpResponse::Ok().json(GenericResponse {
+            message: "Session ended successfully.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to end session {} of user {}: {}",
+                *session_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
@@ -0,0 +1,604 @@
+use crate::agent_info::AgentOptions;
+use crate::api_messages::{
+    AGENT_FAILED_TO_COMMUNICATE, AGENT_INVALID_RESPONSE, BAD_SESSION, FORBIDDEN, GenericResponse,
+    SERVER_ERROR,
+};
+use crate::conversation::{
+    AgentServices, CreateConversationRequest, CreateConversationResponse, start_conversation,
+};
+use crate::login::validate_session;
+use crate::request_id::RequestId;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post, put};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgPool};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// Prompt templates are questions with `{placeholder}` variables that get filled in when a
+// conversation is started from them. A placeholder name may only contain letters, digits, and
+// underscores; any other text in braces is left as is.
+
+/// The message returned when a template doesn't exist or can't be accessed.
+static TEMPLATE_NOT_FOUND: &str = "Template not found.";
+
+/// A saved prompt template.
+#[derive(Serialize, ToSchema)]
+pub struct PromptTemplate {
+    pub(crate) template_id: i32,
+    /// The user who created and owns the template.
+    pub(crate) user_id: i32,
+    pub(crate) title: String,
+    /// The prompt, e.g. "Compare {philosopher_a} and {philosopher_b} on {topic}".
+    pub(crate) body: String,
+    /// Shared templates can be used by every user, but only changed by their owner. Only admins
+    /// may share templates.
+    pub(crate) shared: bool,
+    #[schema(value_type = String, format = "date-time")]
+    pub(crate) created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = "date-time")]
+    pub(crate) updated_at: DateTime<Utc>,
+}
+
+/// A prompt template along with the placeholders it expects.
+#[derive(Serialize, ToSchema)]
+pub struct TemplateResponse {
+    #[serde(flatten)]
+    template: PromptTemplate,
+    /// Names of the placeholders in the template body, in order of first appearance.
+    placeholders: Vec<String>,
+}
+
+impl From<PromptTemplate> for TemplateResponse {
+    fn from(template: PromptTemplate) -> Self {
+        TemplateResponse {
+            placeholders: placeholders(&template.body),
+            template,
+        }
+    }
+}
+
+/// Post request data to create a template.
+#[derive(Deserialize, ToSchema)]
+pub struct CreateTemplateRequest {
+    title: String,
+    body: String,
+    /// Offer the template to every user, only admins may do so.
+    #[serde(default)]
+    shared: bool,
+}
+
+/// Put request data to change a template.
+///
+/// Fields that are left out are kept as they are.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTemplateRequest {
+    title: Option<String>,
+    body: Option<String>,
+    /// Only admins may share a template, anyone may stop sharing their own.
+    shared: Option<bool>,
+}
+
+/// Post request data to start a conversation from a template.
+#[derive(Deserialize, ToSchema)]
+pub struct FromTemplateRequest {
+    template_id: i32,
+    /// Values for the template placeholders.
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    /// Uploaded documents to give Cogito as context.
+    #[serde(default)]
+    document_ids: Vec<i32>,
+    /// How Cogito should answer.
+    #[serde(default)]
+    options: AgentOptions,
+    /// Client chosen UUID to follow the research progress at `/progress/{progress_id}`.
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    progress_id: Option<Uuid>,
+    /// URL of an agent backend from `/agent/status` to pin the conversation to.
+    #[serde(default)]
+    agent_backend: Option<String>,
+}
+
+/// JSON response when a template can't be filled in.
+#[derive(Serialize, ToSchema)]
+pub struct MissingVariablesResponse {
+    message: &'static str,
+    /// Placeholders that were not given a value.
+    missing: Vec<String>,
+}
+
+/// A piece of a parsed template body.
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template body into literal text and placeholders.
+fn segments(body: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+
+        let name = &rest[open + 1..close];
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            segments.push(Segment::Text(&rest[..open]));
+            segments.push(Segment::Placeholder(name));
+        } else {
+            // Not a placeholder, keep the brace as text and continue after it.
+            segments.push(Segment::Text(&rest[..=open]));
+            rest = &rest[open + 1..];
+            continue;
+        }
+
+        rest = &rest[close + 1..];
+    }
+
+    segments.push(Segment::Text(rest));
+    segments
+}
+
+/// Names of the placeholders in a template body, in order of first appearance.
+fn placeholders(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for segment in segments(body) {
+        if let Segment::Placeholder(name) = segment
+            && !names.iter().any(|existing| existing == name)
+        {
+            names.push(name.to_string());
+        }
+    }
+
+    names
+}
+
+/// Fill in a template body, returning the names of any placeholders without a value.
+fn fill_template(body: &str, variables: &HashMap<String, String>) -> Result<String, Vec<String>> {
+    let mut filled = String::with_capacity(body.len());
+    let mut missing: Vec<String> = Vec::new();
+
+    for segment in segments(body) {
+        match segment {
+            Segment::Text(text) => filled.push_str(text),
+            Segment::Placeholder(name) => match variables.get(name) {
+                Some(value) => filled.push_str(value),
+                None if !missing.iter().any(|existing| existing == name) => {
+                    missing.push(name.to_string())
+                }
+                None => {}
+            },
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(filled)
+    } else {
+        Err(missing)
+    }
+}
+
+/// Fetch a template the user may use, either their own or a shared one.
+async fn fetch_template(
+    template_id: i32,
+    user_id: i32,
+    db: &PgPool,
+) -> Result<PromptTemplate, HttpResponse> {
+    match sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        select * from prompt_templates
+        where template_id = $1 and (user_id = $2 or shared)
+        "#,
+        template_id,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    {
+        Ok(template) => Ok(template),
+        Err(Error::RowNotFound) => Err(HttpResponse::NotFound().json(GenericResponse {
+            message: TEMPLATE_NOT_FOUND,
+        })),
+        Err(e) => {
+            error!("Failed to retrieve template {}: {}", template_id, e);
+            Err(HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            }))
+        }
+    }
+}
+
+/// List the user's own templates and every shared template.
+#[utoipa::path(
+    get,
+    path = "/templates",
+    responses(
+        (status = 200, description = "Templates retrieved successfully.", body = Vec<TemplateResponse>),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[get("/templates")]
+pub async fn get_templates(req: HttpRequest, db: Data<PgPool>) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        select * from prompt_templates
+        where user_id = $1 or shared
+        order by title, template_id
+        "#,
+        user.user_id
+    )
+    .fetch_all(db.get_ref())
+    .await
+    {
+        Ok(templates) => HttpResponse::Ok().json(
+            templates
+                .into_iter()
+                .map(TemplateResponse::from)
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            error!(
+                "Failed to retrieve templates for user {}: {}",
+                user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Save a new prompt template.
+#[utoipa::path(
+    post,
+    path = "/templates",
+    request_body = CreateTemplateRequest,
+    responses(
+        (status = 200, description = "Template created successfully.", body = TemplateResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[post("/templates")]
+pub async fn create_template(
+    req: HttpRequest,
+    info: Json<CreateTemplateRequest>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    // Every user would see a shared template, so only admins get to publish them.
+    if info.shared && !user.admin {
+        return HttpResponse::Forbidden().json(GenericResponse { message: FORBIDDEN });
+    }
+
+    match sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        insert into prompt_templates (user_id, title, body, shared)
+        values ($1, $2, $3, $4)
+        returning *
+        "#,
+        user.user_id,
+        info.title,
+        info.body,
+        info.shared
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(template) => HttpResponse::Ok().json(TemplateResponse::from(template)),
+        Err(e) => {
+            error!(
+                "Failed to create template for user {}: {}",
+                user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Get a template by its ID.
+#[utoipa::path(
+    get,
+    path = "/templates/{template_id}",
+    params(
+        ("template_id" = i32, Path, description = "The ID of the template to retrieve.")
+    ),
+    responses(
+        (status = 200, description = "Template retrieved successfully.", body = TemplateResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = TEMPLATE_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[get("/templates/{template_id}")]
+pub async fn get_template(
+    template_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match fetch_template(*template_id, user.user_id, db.get_ref()).await {
+        Ok(template) => HttpResponse::Ok().json(TemplateResponse::from(template)),
+        Err(e) => e,
+    }
+}
+
+/// Change one of the user's own templates.
+#[utoipa::path(
+    put,
+    path = "/templates/{template_id}",
+    params(
+        ("template_id" = i32, Path, description = "The ID of the template to update.")
+    ),
+    request_body = UpdateTemplateRequest,
+    responses(
+        (status = 200, description = "Template updated successfully.", body = TemplateResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 403, description = FORBIDDEN, body = GenericResponse),
+        (status = 404, description = TEMPLATE_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[put("/templates/{template_id}")]
+pub async fn update_template(
+    template_id: Path<i32>,
+    req: HttpRequest,
+    info: Json<UpdateTemplateRequest>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    if info.shared == Some(true) && !user.admin {
+        return HttpResponse::Forbidden().json(GenericResponse { message: FORBIDDEN });
+    }
+
+    match sqlx::query_as!(
+        PromptTemplate,
+        r#"
+        update prompt_templates
+        set title = coalesce($1, title),
+            body = coalesce($2, body),
+            shared = coalesce($3, shared),
+            updated_at = $4
+        where template_id = $5 and user_id = $6
+        returning *
+        "#,
+        info.title,
+        info.body,
+        info.shared,
+        Utc::now(),
+        *template_id,
+        user.user_id
+    )
+    .fetch_one(db.get_ref())
+    .await
+    {
+        Ok(template) => HttpResponse::Ok().json(TemplateResponse::from(template)),
+        Err(Error::RowNotFound) => HttpResponse::NotFound().json(GenericResponse {
+            message: TEMPLATE_NOT_FOUND,
+        }),
+        Err(e) => {
+            error!(
+                "Failed to update template {} for user {}: {}",
+                *template_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Delete one of the user's own templates.
+#[utoipa::path(
+    delete,
+    path = "/templates/{template_id}",
+    params(
+        ("template_id" = i32, Path, description = "The ID of the template to delete.")
+    ),
+    responses(
+        (status = 200, description = "Template deleted successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = TEMPLATE_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[delete("/templates/{template_id}")]
+pub async fn delete_template(
+    template_id: Path<i32>,
+    req: HttpRequest,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    match sqlx::query!(
+        "delete from prompt_templates where template_id = $1 and user_id = $2",
+        *template_id,
+        user.user_id
+    )
+    .execute(db.get_ref())
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::NotFound().json(GenericResponse {
+                message: TEMPLATE_NOT_FOUND,
+            })
+        }
+        Ok(_) => HttpResponse::Ok().json(GenericResponse {
+            message: "Template deleted successfully.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to delete template {} for user {}: {}",
+                *template_id, user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
+
+/// Start a new conversation with Cogito from a prompt template.
+///
+/// The template's placeholders are filled in with the given variables and the result is used as
+/// the initial message, exactly as if it had been sent to `/create_conversation`.
+#[utoipa::path(
+    post,
+    path = "/conversations/from_template",
+    request_body = FromTemplateRequest,
+    responses(
+        (status = 200, description = "Conversation created successfully.", body = CreateConversationResponse),
+        (status = 400, description = "A template placeholder was not given a value.", body = MissingVariablesResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 404, description = TEMPLATE_NOT_FOUND, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+        (status = 502, description = AGENT_INVALID_RESPONSE, body = GenericResponse),
+        (status = 503, description = AGENT_FAILED_TO_COMMUNICATE, body = GenericResponse),
+    )
+)]
+#[post("/conversations/from_template")]
+pub async fn conversation_from_template(
+    req: HttpRequest,
+    info: Json<FromTemplateRequest>,
+    db: Data<PgPool>,
+    services: AgentServices,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let FromTemplateRequest {
+        template_id,
+        variables,
+        document_ids,
+        options,
+        progress_id,
+        agent_backend,
+    } = info.into_inner();
+
+    let template = match fetch_template(template_id, user.user_id, db.get_ref()).await {
+        Ok(template) => template,
+        Err(e) => return e,
+    };
+
+    let initial_message = match fill_template(&template.body, &variables) {
+        Ok(message) => message,
+        Err(missing) => {
+            return HttpResponse::BadRequest().json(MissingVariablesResponse {
+                message: "A template placeholder was not given a value.",
+                missing,
+            });
+        }
+    };
+
+    let conversation_info = CreateConversationRequest {
+        initial_message,
+        document_ids,
+        options,
+        progress_id,
+        agent_backend,
+    };
+
+    match start_conversation(
+        &user,
+        conversation_info,
+        RequestId::of(&req),
+        db.get_ref(),
+        &services,
+    )
+    .await
+    {
+        Ok(conversation_id) => {
+            HttpResponse::Ok().json(CreateConversationResponse { conversation_id })
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fill_template, placeholders};
+    use std::collections::HashMap;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Test that a placeholder used several times is filled in everywhere.
+    #[test]
+    fn test_repeated_placeholder() {
+        let body = "Compare {thinker} and Kant. Why did {thinker} disagree?";
+
+        assert_eq!(placeholders(body), vec!["thinker"]);
+        assert_eq!(
+            fill_template(body, &variables(&[("thinker", "Hume")])).unwrap(),
+            "Compare Hume and Kant. Why did Hume disagree?"
+        );
+    }
+
+    /// Test that placeholders without a value are reported once each, in order.
+    #[test]
+    fn test_missing_variables() {
+        let body = "{b} {a} {b} {c}";
+
+        let missing = fill_template(body, &variables(&[("c", "set"), ("unused", "x")]));
+        assert_eq!(missing.unwrap_err(), vec!["b", "a"]);
+    }
+
+    /// Test that braces that don't form a placeholder are kept as text.
+    #[test]
+    fn test_literal_braces() {
+        let values = variables(&[("name", "Plato")]);
+
+        assert_eq!(
+            fill_template("Hello {name", &values).unwrap(),
+            "Hello {name"
+        );
+        assert_eq!(
+            fill_template("{} {not a name} {name}", &values).unwrap(),
+            "{} {not a name} Plato"
+        );
+        // Only the innermost braces are the placeholder.
+        assert_eq!(fill_template("{{name}}", &values).unwrap(), "{Plato}");
+        assert!(placeholders("Hello {name").is_empty());
+    }
+}
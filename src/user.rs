@@ -1,8 +1,9 @@
-use crate::api_messages::{GenericResponse, SERVER_ERROR, UNAUTHORIZED};
+use crate::api_messages::{BAD_SESSION, GenericResponse, SERVER_ERROR, UNAUTHORIZED};
 use crate::login::validate_session;
-use actix_web::web::{Data, Path};
-use actix_web::{HttpRequest, HttpResponse, Responder, get};
+use actix_web::web::{Data, Form, Json, Path};
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, get, put};
 use chrono::{DateTime, Utc};
+use log::error;
 use serde::{Deserialize, Serialize};
 use sqlx::{Error, FromRow, PgPool};
 use utoipa::ToSchema;
@@ -17,10 +18,22 @@ pub struct User {
     pub(crate) user_pass: String,
     #[schema(value_type = String, format = "date-time")]
     pub(crate) user_last_login: DateTime<Utc>,
-    #[schema(value_type = String, format = "uuid", nullable)]
-    pub(crate) login_id: Option<Uuid>,
     pub(crate) verified: bool,
     pub(crate) admin: bool,
+    /// Identifies the user to the Cogito agent without revealing who they are.
+    ///
+    /// Never sent to clients, anyone knowing it could link the agent's records back to the user.
+    #[serde(skip_serializing)]
+    pub(crate) pseudonym: Uuid,
+    /// Always ask the agent instead of reusing answers to identical questions.
+    pub(crate) answer_cache_opt_out: bool,
+}
+
+/// Put request data to change whether a user's questions may be answered from the cache.
+#[derive(Deserialize, ToSchema)]
+pub struct AnswerCacheSetting {
+    /// Whether identical questions may be answered with a stored answer.
+    enabled: bool,
 }
 
 #[utoipa::path(
@@ -65,3 +78,52 @@ async fn user_by_id(req: HttpRequest, id: Path<i32>, db: Data<PgPool>) -> impl R
         }),
     }
 }
+
+/// Choose whether questions may be answered with a stored answer to an identical question.
+///
+/// Cached answers are quicker, but a user may want a fresh answer every time.
+#[utoipa::path(
+    put,
+    path = "/users/me/answer_cache",
+    request_body = AnswerCacheSetting,
+    responses(
+        (status = 200, description = "Setting saved successfully.", body = GenericResponse),
+        (status = 403, description = BAD_SESSION, body = GenericResponse),
+        (status = 500, description = SERVER_ERROR, body = GenericResponse),
+    )
+)]
+#[put("/users/me/answer_cache")]
+pub async fn set_answer_cache(
+    req: HttpRequest,
+    info: Either<Json<AnswerCacheSetting>, Form<AnswerCacheSetting>>,
+    db: Data<PgPool>,
+) -> impl Responder {
+    let user = match validate_session(&req, db.get_ref()).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+
+    let AnswerCacheSetting { enabled } = info.into_inner();
+
+    match sqlx::query!(
+        "update users set answer_cache_opt_out = $1 where user_id = $2",
+        !enabled,
+        user.user_id
+    )
+    .execute(db.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse {
+            message: "Setting saved successfully.",
+        }),
+        Err(e) => {
+            error!(
+                "Failed to change the answer cache setting for user {}: {}",
+                user.user_name, e
+            );
+            HttpResponse::InternalServerError().json(GenericResponse {
+                message: SERVER_ERROR,
+            })
+        }
+    }
+}
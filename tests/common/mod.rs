@@ -0,0 +1,28 @@
+// Helpers shared by the integration tests. Each test crate compiles this separately and only uses
+// some of it.
+#![allow(dead_code)]
+
+use cogito_api::agent::{AgentConfig, CogitoAgent};
+use cogito_api::mock_agent::{MockAgent, MockConfig};
+use tokio::net::TcpListener;
+
+/// Start a mock agent on a free port, returning it with the URL it is served on.
+pub async fn start_mock(config: MockConfig) -> (MockAgent, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let mock = MockAgent::new(config);
+    tokio::spawn(mock.clone().serve_with_listener(listener));
+
+    (mock, format!("http://{}", address))
+}
+
+/// Connect an agent pool to a single backend, without retries so failures show up directly.
+pub fn connect_agent(url: String) -> CogitoAgent {
+    CogitoAgent::connect_lazy(AgentConfig {
+        urls: vec![url],
+        max_retries: 0,
+        ..AgentConfig::default()
+    })
+    .unwrap()
+}
@@ -0,0 +1,108 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{connect_agent, start_mock};
+    use cogito_api::agent::CogitoAgent;
+    use cogito_api::answer::{AgentMessage, Role};
+    use cogito_api::context::{ContextConfig, ContextStrategy, prepare_context};
+    use cogito_api::mock_agent::MockConfig;
+
+    /// Start a mock agent with the default behavior and connect an agent pool to it.
+    async fn mock_agent() -> CogitoAgent {
+        let (_, url) = start_mock(MockConfig::default()).await;
+        connect_agent(url)
+    }
+
+    /// Ten alternating messages of about a hundred tokens each, numbered in their content.
+    fn long_conversation() -> Vec<AgentMessage> {
+        (0..10)
+            .map(|i| AgentMessage {
+                role: if i % 2 == 0 {
+                    Role::User
+                } else {
+                    Role::Assistant
+                },
+                content: format!("{} {}", i, "x".repeat(400)),
+                citations: Vec::new(),
+                trace: Vec::new(),
+                provenance: None,
+                context: None,
+            })
+            .collect()
+    }
+
+    fn config(strategy: ContextStrategy) -> ContextConfig {
+        ContextConfig {
+            max_tokens: 350,
+            strategy,
+        }
+    }
+
+    /// Test that short history is sent as is and long history is cut to the newest messages.
+    #[tokio::test]
+    async fn test_sliding_window() {
+        let agent = mock_agent().await;
+        let messages = long_conversation();
+        let config = config(ContextStrategy::SlidingWindow);
+
+        let prepared = prepare_context(&agent, &config, &messages[..2], None).await;
+        assert_eq!(prepared.history.len(), 2);
+        assert!(prepared.applied.strategy.is_none());
+
+        let prepared = prepare_context(&agent, &config, &messages, None).await;
+        assert_eq!(prepared.history.len(), 3);
+        assert!(prepared.history[0].content.starts_with("7 "));
+        assert_eq!(
+            prepared.applied.strategy,
+            Some(ContextStrategy::SlidingWindow)
+        );
+        assert_eq!(prepared.applied.omitted_messages, 7);
+        assert!(prepared.applied.estimated_tokens <= config.max_tokens);
+    }
+
+    /// Test that the first message is always kept.
+    #[tokio::test]
+    async fn test_pinned_first() {
+        let agent = mock_agent().await;
+        let messages = long_conversation();
+
+        let prepared = prepare_context(
+            &agent,
+            &config(ContextStrategy::PinnedFirst),
+            &messages,
+            None,
+        )
+        .await;
+        assert_eq!(prepared.history.len(), 3);
+        assert!(prepared.history[0].content.starts_with("0 "));
+        assert!(prepared.history[1].content.starts_with("8 "));
+        assert_eq!(prepared.applied.omitted_messages, 7);
+    }
+
+    /// Test that older messages are summarized once and the stored summary is reused.
+    #[tokio::test]
+    async fn test_summarize() {
+        let agent = mock_agent().await;
+        let messages = long_conversation();
+        let config = ContextConfig {
+            max_tokens: 700,
+            strategy: ContextStrategy::Summarize,
+        };
+
+        let prepared = prepare_context(&agent, &config, &messages, None).await;
+        let summary = prepared
+            .new_summary
+            .expect("A summary should have been made.");
+        assert!(!prepared.summary.is_empty());
+        assert_eq!(prepared.applied.summarized_messages, summary.covers);
+        assert_eq!(
+            summary.covers + prepared.applied.omitted_messages + prepared.history.len(),
+            messages.len()
+        );
+
+        let prepared = prepare_context(&agent, &config, &messages, Some(&summary)).await;
+        assert!(prepared.new_summary.is_none());
+        assert_eq!(prepared.summary, summary.content);
+    }
+}
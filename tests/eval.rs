@@ -0,0 +1,54 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{connect_agent, start_mock};
+    use cogito_api::eval::{BenchmarkQuestion, DiffStatus, diff, run_benchmark};
+    use cogito_api::mock_agent::{MockConfig, MockResponse};
+    use tonic::Code;
+
+    fn benchmark() -> Vec<BenchmarkQuestion> {
+        ["What is virtue?", "What is justice?"]
+            .iter()
+            .enumerate()
+            .map(|(i, content)| BenchmarkQuestion {
+                id: format!("q{}", i),
+                content: content.to_string(),
+                model: None,
+            })
+            .collect()
+    }
+
+    /// Test that a benchmark run records every answer and a failing candidate is a regression.
+    #[tokio::test]
+    async fn test_benchmark_regression() {
+        let (_, url) = start_mock(MockConfig::default()).await;
+        let baseline = run_benchmark(&connect_agent(url), &benchmark()).await;
+
+        assert_eq!(baseline.records.len(), 2);
+        let record = &baseline.records[0];
+        assert!(record.answer.is_some());
+        assert!(record.latency_ms.is_some());
+        assert!(record.citations > 0);
+        assert_eq!(record.model.as_deref(), Some("mock-deep"));
+
+        let (_, url) = start_mock(MockConfig {
+            script: vec![
+                MockResponse::Answer("Virtue is excellence of character.".to_string()),
+                MockResponse::Error {
+                    code: Code::Internal as i32,
+                    message: "Out of ideas.".to_string(),
+                },
+            ],
+            ..MockConfig::default()
+        })
+        .await;
+        let candidate = run_benchmark(&connect_agent(url), &benchmark()).await;
+
+        let report = diff(&baseline, &candidate);
+        assert_eq!(report.entries[0].status, DiffStatus::Changed);
+        assert_eq!(report.entries[1].status, DiffStatus::Broken);
+        assert!(report.has_regressions());
+        assert!(!diff(&baseline, &baseline).has_regressions());
+    }
+}
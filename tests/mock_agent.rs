@@ -0,0 +1,204 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::start_mock;
+    use cogito_api::agent::{AgentConfig, CallContext, CogitoAgent};
+    use cogito_api::answer::parse_answer;
+    use cogito_api::mock_agent::{MockAgent, MockConfig, MockResponse};
+    use cogito_api::proto::cogito_client::CogitoClient;
+    use cogito_api::proto::{ActionDecision, Question, ask_event};
+    use std::time::Duration;
+    use tonic::Code;
+    use tonic::transport::Channel;
+    use uuid::Uuid;
+
+    /// Start a mock agent on a free port and connect to it.
+    async fn connect_mock(config: MockConfig) -> (MockAgent, CogitoClient<Channel>) {
+        let (agent, url) = start_mock(config).await;
+        let client = CogitoClient::connect(url).await.unwrap();
+        (agent, client)
+    }
+
+    fn question(content: &str) -> Question {
+        Question {
+            content: content.to_string(),
+            documents: Vec::new(),
+            options: None,
+            history: Vec::new(),
+            summary: String::new(),
+        }
+    }
+
+    /// Test that scripted replies are given in order and then fall back to valid echo answers.
+    #[tokio::test]
+    async fn test_scripted_responses() {
+        let (agent, mut client) = connect_mock(MockConfig {
+            script: vec![
+                MockResponse::Answer("Act only according to that maxim...".to_string()),
+                MockResponse::Error {
+                    code: Code::ResourceExhausted as i32,
+                    message: "Too many questions.".to_string(),
+                },
+            ],
+            ..MockConfig::default()
+        })
+        .await;
+
+        let answer = client
+            .ask(question("What is the categorical imperative?"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(answer.agent_version.starts_with("mock-"));
+        assert_eq!(answer.model, "mock-deep");
+        let conversation = parse_answer(&answer).unwrap();
+        let reply = conversation.messages.last().unwrap();
+        assert_eq!(reply.content, "Act only according to that maxim...");
+        assert!(!reply.citations.is_empty());
+
+        let status = client.ask(question("And again?")).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let answer = client.ask(question("Once more?")).await.unwrap();
+        assert!(parse_answer(&answer.into_inner()).is_ok());
+
+        assert_eq!(agent.questions().len(), 3);
+    }
+
+    /// Test that every question fails when the error rate is 1.
+    #[tokio::test]
+    async fn test_error_injection() {
+        let (_, mut client) = connect_mock(MockConfig {
+            error_rate: 1.0,
+            ..MockConfig::default()
+        })
+        .await;
+
+        let status = client.ask(question("Is anyone there?")).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    /// Test that progress is streamed before the answer.
+    #[tokio::test]
+    async fn test_streamed_progress() {
+        let config = MockConfig::default();
+        let steps = config.progress.len();
+        let (_, mut client) = connect_mock(config).await;
+
+        let mut stream = client
+            .ask_with_progress(question("What is virtue?"))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut events = Vec::new();
+        while let Some(event) = stream.message().await.unwrap() {
+            events.push(event.event.unwrap());
+        }
+
+        assert_eq!(events.len(), steps + 1);
+        assert!(matches!(events[0], ask_event::Event::Progress(_)));
+        match events.last().unwrap() {
+            ask_event::Event::Answer(answer) => assert!(parse_answer(answer).is_ok()),
+            _ => panic!("Stream did not end with an answer."),
+        }
+    }
+
+    /// Test that the stream waits for approval and reports the decision.
+    #[tokio::test]
+    async fn test_action_approval() {
+        let (agent, mut client) = connect_mock(MockConfig {
+            progress: Vec::new(),
+            approvals: vec!["Fetch the Akademie edition".to_string()],
+            ..MockConfig::default()
+        })
+        .await;
+
+        let mut stream = client
+            .ask_with_progress(question("What did Kant write in 1785?"))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let action_id = match stream.message().await.unwrap().unwrap().event.unwrap() {
+            ask_event::Event::ApprovalRequest(request) => request.action_id,
+            _ => panic!("Stream did not start with an approval request."),
+        };
+
+        client
+            .resolve_action(ActionDecision {
+                action_id: action_id.clone(),
+                approved: true,
+                reason: String::new(),
+            })
+            .await
+            .unwrap();
+
+        match stream.message().await.unwrap().unwrap().event.unwrap() {
+            ask_event::Event::Progress(progress) => {
+                assert!(progress.description.starts_with("Approved"))
+            }
+            _ => panic!("The decision was not reported."),
+        }
+        assert_eq!(agent.decisions().len(), 1);
+
+        // Deciding twice is an error, the agent is no longer waiting.
+        let status = client
+            .resolve_action(ActionDecision {
+                action_id,
+                approved: false,
+                reason: String::new(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    /// Test that a question is only asked again if the agent couldn't be reached.
+    #[tokio::test]
+    async fn test_question_retries() {
+        let (mock, url) = start_mock(MockConfig {
+            script: vec![
+                MockResponse::Error {
+                    code: Code::Unavailable as i32,
+                    message: "Restarting.".to_string(),
+                },
+                MockResponse::Answer("Virtue is a mean between extremes.".to_string()),
+                MockResponse::Error {
+                    code: Code::Aborted as i32,
+                    message: "Research was interrupted.".to_string(),
+                },
+            ],
+            ..MockConfig::default()
+        })
+        .await;
+        let agent = CogitoAgent::connect_lazy(AgentConfig {
+            urls: vec![url],
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(1),
+            ..AgentConfig::default()
+        })
+        .unwrap();
+        let context = CallContext {
+            request_id: "retries".to_string(),
+            user: Uuid::new_v4(),
+            conversation: Uuid::new_v4(),
+            backend: None,
+        };
+
+        let answer = agent
+            .ask(question("What is virtue?"), &context)
+            .await
+            .unwrap();
+        assert!(parse_answer(&answer).is_ok());
+        assert_eq!(mock.questions().len(), 2);
+
+        let status = agent
+            .ask(question("And courage?"), &context)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Aborted);
+        assert_eq!(mock.questions().len(), 3);
+    }
+}
@@ -0,0 +1,255 @@
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[derive(Serialize)]
+    struct RegisterRequest {
+        email: String,
+        phone_number: String,
+        username: String,
+        password: String,
+    }
+
+    #[derive(Serialize)]
+    struct TokenLoginRequest {
+        username: String,
+        password: String,
+        token: bool,
+    }
+
+    #[derive(Deserialize)]
+    struct TokenLoginResponse {
+        token: String,
+    }
+
+    #[derive(Deserialize)]
+    struct CreateConversationResponse {
+        conversation_id: i32,
+    }
+
+    #[derive(Deserialize)]
+    struct RevisionSummary {
+        revision_number: i32,
+        revision_kind: String,
+        conversation_title: String,
+    }
+
+    #[derive(Deserialize)]
+    struct ConversationRevision {
+        conversation_id: i32,
+        revision_number: i32,
+        conversation_title: String,
+        conversation: serde_json::Value,
+    }
+
+    /// Register a new user and log in with a bearer token.
+    async fn login(client: &Client, prefix: &str) -> String {
+        let unique_id = Uuid::new_v4().to_string();
+        let register_req = RegisterRequest {
+            email: format!("{}+{}@example.com", prefix, unique_id),
+            phone_number: format!("555-1357-{}", &unique_id[..6]),
+            username: format!("{}_test_{}", prefix, &unique_id[..6]),
+            password: "sherm".into(),
+        };
+
+        let register_resp = client
+            .post("http://127.0.0.1:8080/register")
+            .json(&register_req)
+            .send()
+            .await
+            .expect("Failed to send register request");
+        assert!(register_resp.status().is_success(), "Registration failed");
+
+        let login_resp = client
+            .post("http://127.0.0.1:8080/login")
+            .json(&TokenLoginRequest {
+                username: register_req.username,
+                password: register_req.password,
+                token: true,
+            })
+            .send()
+            .await
+            .expect("Failed to send login request");
+        assert!(login_resp.status().is_success(), "Login failed");
+
+        let login_body: TokenLoginResponse = login_resp
+            .json()
+            .await
+            .expect("Failed to deserialize login response");
+        login_body.token
+    }
+
+    async fn create_conversation(client: &Client, token: &str) -> i32 {
+        let create_resp = client
+            .post("http://127.0.0.1:8080/create_conversation")
+            .bearer_auth(token)
+            .json(&json!({ "initial_message": "What is virtue?" }))
+            .send()
+            .await
+            .expect("Failed to send create conversation request");
+        assert!(
+            create_resp.status().is_success(),
+            "Creating a conversation failed"
+        );
+
+        let create_body: CreateConversationResponse = create_resp
+            .json()
+            .await
+            .expect("Failed to deserialize create conversation response");
+        create_body.conversation_id
+    }
+
+    async fn rename(client: &Client, token: &str, conversation_id: i32, title: &str) {
+        let rename_resp = client
+            .put(format!(
+                "http://127.0.0.1:8080/conversation/{}/title",
+                conversation_id
+            ))
+            .bearer_auth(token)
+            .json(&json!({ "conversation_title": title }))
+            .send()
+            .await
+            .expect("Failed to send rename request");
+        assert!(rename_resp.status().is_success(), "Renaming failed");
+    }
+
+    async fn revisions(client: &Client, token: &str, conversation_id: i32) -> Vec<RevisionSummary> {
+        let revisions_resp = client
+            .get(format!(
+                "http://127.0.0.1:8080/conversation/{}/revisions",
+                conversation_id
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to send revisions request");
+        assert!(
+            revisions_resp.status().is_success(),
+            "Listing revisions failed"
+        );
+
+        revisions_resp
+            .json()
+            .await
+            .expect("Failed to deserialize revisions")
+    }
+
+    /// Test that creating and renaming a conversation are recorded as numbered revisions.
+    #[tokio::test]
+    async fn test_creation_and_rename_revisions() {
+        let client = Client::new();
+        let token = login(&client, "revisions").await;
+        let conversation_id = create_conversation(&client, &token).await;
+
+        let created = revisions(&client, &token, conversation_id).await;
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].revision_number, 1);
+        assert_eq!(created[0].revision_kind, "created");
+
+        rename(&client, &token, conversation_id, "Virtue").await;
+        rename(&client, &token, conversation_id, "Virtue ethics").await;
+
+        let renamed = revisions(&client, &token, conversation_id).await;
+        let numbers: Vec<i32> = renamed.iter().map(|r| r.revision_number).collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        assert_eq!(renamed[1].revision_kind, "renamed");
+        assert_eq!(renamed[1].conversation_title, "Virtue");
+        assert_eq!(renamed[2].conversation_title, "Virtue ethics");
+
+        // Earlier revisions still show the conversation as it was.
+        let revision_resp = client
+            .get(format!(
+                "http://127.0.0.1:8080/conversation/{}/revisions/2",
+                conversation_id
+            ))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("Failed to send revision request");
+        assert!(
+            revision_resp.status().is_success(),
+            "Viewing revision failed"
+        );
+
+        let revision: ConversationRevision = revision_resp
+            .json()
+            .await
+            .expect("Failed to deserialize revision");
+        assert_eq!(revision.conversation_id, conversation_id);
+        assert_eq!(revision.revision_number, 2);
+        assert_eq!(revision.conversation_title, "Virtue");
+        assert!(revision.conversation["messages"].is_array());
+
+        let missing_resp = client
+            .get(format!(
+                "http://127.0.0.1:8080/conversation/{}/revisions/4",
+                conversation_id
+            ))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("Failed to send revision request");
+        assert_eq!(missing_resp.status().as_u16(), 404);
+
+        // The history is kept, but not served once the conversation is gone.
+        let delete_resp = client
+            .delete(format!(
+                "http://127.0.0.1:8080/conversation/{}",
+                conversation_id
+            ))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("Failed to send delete request");
+        assert!(delete_resp.status().is_success(), "Deleting failed");
+
+        let deleted_resp = client
+            .get(format!(
+                "http://127.0.0.1:8080/conversation/{}/revisions",
+                conversation_id
+            ))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .expect("Failed to send revisions request");
+        assert_eq!(deleted_resp.status().as_u16(), 404);
+    }
+
+    /// Test that nobody but the owner can read a conversation's revisions.
+    #[tokio::test]
+    async fn test_revision_ownership() {
+        let client = Client::new();
+        let owner = login(&client, "owner").await;
+        let stranger = login(&client, "stranger").await;
+        let conversation_id = create_conversation(&client, &owner).await;
+
+        for url in [
+            format!(
+                "http://127.0.0.1:8080/conversation/{}/revisions",
+                conversation_id
+            ),
+            format!(
+                "http://127.0.0.1:8080/conversation/{}/revisions/1",
+                conversation_id
+            ),
+        ] {
+            let anonymous_resp = client
+                .get(&url)
+                .send()
+                .await
+                .expect("Failed to send revisions request");
+            assert_eq!(anonymous_resp.status().as_u16(), 401);
+
+            let stranger_resp = client
+                .get(&url)
+                .bearer_auth(&stranger)
+                .send()
+                .await
+                .expect("Failed to send revisions request");
+            assert_eq!(stranger_resp.status().as_u16(), 404);
+        }
+    }
+}
@@ -23,11 +23,29 @@ mod tests {
         password: String,
     }
 
+    #[derive(Serialize)]
+    struct TokenLoginRequest {
+        username: String,
+        password: String,
+        token: bool,
+    }
+
     #[derive(Deserialize)]
     struct LoginResponse {
         message: String,
     }
 
+    #[derive(Deserialize)]
+    struct TokenLoginResponse {
+        token: String,
+    }
+
+    #[derive(Deserialize)]
+    struct SessionResponse {
+        session_id: i32,
+        current: bool,
+    }
+
     /// Test registration, login, and session-protected route
     #[tokio::test]
     async fn test_register_login_and_access_protected_route() {
@@ -111,4 +129,217 @@ mod tests {
 
         println!("Protected endpoint response: {}", content);
     }
+
+    /// Test logging in for a bearer token instead of a cookie.
+    #[tokio::test]
+    async fn test_bearer_token_login() {
+        // No cookie store, so only the token can authenticate.
+        let client = Client::new();
+
+        let unique_id = Uuid::new_v4().to_string();
+        let register_req = RegisterRequest {
+            email: format!("bearer+{}@example.com", unique_id),
+            phone_number: format!("555-4321-{}", &unique_id[..6]),
+            username: format!("bearer_test_{}", &unique_id[..6]),
+            password: "sherm".into(),
+        };
+
+        let register_resp = client
+            .post("http://127.0.0.1:8080/register")
+            .json(&register_req)
+            .send()
+            .await
+            .expect("Failed to send register request");
+        assert!(register_resp.status().is_success(), "Registration failed");
+
+        let login_resp = client
+            .post("http://127.0.0.1:8080/login")
+            .json(&TokenLoginRequest {
+                username: register_req.username.clone(),
+                password: register_req.password.clone(),
+                token: true,
+            })
+            .send()
+            .await
+            .expect("Failed to send login request");
+
+        assert!(login_resp.status().is_success(), "Login failed");
+        assert!(
+            login_resp.headers().get("set-cookie").is_none(),
+            "A cookie was set for a token login"
+        );
+
+        let login_body: TokenLoginResponse = login_resp
+            .json()
+            .await
+            .expect("Failed to deserialize login response");
+
+        let protected_url = "http://127.0.0.1:8080/users/1";
+
+        let anonymous_resp = client
+            .get(protected_url)
+            .send()
+            .await
+            .expect("Failed to send request to protected endpoint");
+        assert_eq!(anonymous_resp.status().as_u16(), 401);
+
+        let protected_resp = client
+            .get(protected_url)
+            .bearer_auth(&login_body.token)
+            .send()
+            .await
+            .expect("Failed to send request to protected endpoint");
+
+        assert!(
+            protected_resp.status().is_success(),
+            "Access denied with a bearer token"
+        );
+    }
+
+    /// Test that logging in on another device keeps the first session valid.
+    #[tokio::test]
+    async fn test_concurrent_sessions() {
+        let client = Client::new();
+
+        let unique_id = Uuid::new_v4().to_string();
+        let register_req = RegisterRequest {
+            email: format!("devices+{}@example.com", unique_id),
+            phone_number: format!("555-2468-{}", &unique_id[..6]),
+            username: format!("devices_test_{}", &unique_id[..6]),
+            password: "sherm".into(),
+        };
+
+        let register_resp = client
+            .post("http://127.0.0.1:8080/register")
+            .json(&register_req)
+            .send()
+            .await
+            .expect("Failed to send register request");
+        assert!(register_resp.status().is_success(), "Registration failed");
+
+        let mut tokens = Vec::new();
+        for _ in 0..2 {
+            let login_resp = client
+                .post("http://127.0.0.1:8080/login")
+                .json(&TokenLoginRequest {
+                    username: register_req.username.clone(),
+                    password: register_req.password.clone(),
+                    token: true,
+                })
+                .send()
+                .await
+                .expect("Failed to send login request");
+            assert!(login_resp.status().is_success(), "Login failed");
+
+            let login_body: TokenLoginResponse = login_resp
+                .json()
+                .await
+                .expect("Failed to deserialize login response");
+            tokens.push(login_body.token);
+        }
+
+        assert_ne!(tokens[0], tokens[1]);
+
+        for token in &tokens {
+            let protected_resp = client
+                .get("http://127.0.0.1:8080/users/1")
+                .bearer_auth(token)
+                .send()
+                .await
+                .expect("Failed to send request to protected endpoint");
+
+            assert!(
+                protected_resp.status().is_success(),
+                "A session was ended by logging in again"
+            );
+        }
+    }
+
+    /// Test that ending a session from another device and logging out take effect immediately.
+    #[tokio::test]
+    async fn test_session_revocation() {
+        let client = Client::new();
+
+        let unique_id = Uuid::new_v4().to_string();
+        let register_req = RegisterRequest {
+            email: format!("revoke+{}@example.com", unique_id),
+            phone_number: format!("555-1357-{}", &unique_id[..6]),
+            username: format!("revoke_test_{}", &unique_id[..6]),
+            password: "sherm".into(),
+        };
+
+        let register_resp = client
+            .post("http://127.0.0.1:8080/register")
+            .json(&register_req)
+            .send()
+            .await
+            .expect("Failed to send register request");
+        assert!(register_resp.status().is_success(), "Registration failed");
+
+        let mut tokens = Vec::new();
+        for _ in 0..2 {
+            let login_body: TokenLoginResponse = client
+                .post("http://127.0.0.1:8080/login")
+                .json(&TokenLoginRequest {
+                    username: register_req.username.clone(),
+                    password: register_req.password.clone(),
+                    token: true,
+                })
+                .send()
+                .await
+                .expect("Failed to send login request")
+                .json()
+                .await
+                .expect("Failed to deserialize login response");
+            tokens.push(login_body.token);
+        }
+        let (laptop, phone) = (&tokens[0], &tokens[1]);
+
+        let sessions: Vec<SessionResponse> = client
+            .get("http://127.0.0.1:8080/sessions")
+            .bearer_auth(laptop)
+            .send()
+            .await
+            .expect("Failed to list sessions")
+            .json()
+            .await
+            .expect("Failed to deserialize sessions");
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.iter().filter(|session| session.current).count(), 1);
+
+        // End the phone's session from the laptop.
+        let other = sessions.iter().find(|session| !session.current).unwrap();
+        let delete_resp = client
+            .delete(format!(
+                "http://127.0.0.1:8080/sessions/{}",
+                other.session_id
+            ))
+            .bearer_auth(laptop)
+            .send()
+            .await
+            .expect("Failed to end session");
+        assert!(delete_resp.status().is_success(), "Ending a session failed");
+
+        let is_valid = |token: &String| {
+            let request = client
+                .get("http://127.0.0.1:8080/sessions")
+                .bearer_auth(token)
+                .send();
+            async move { request.await.unwrap().status().is_success() }
+        };
+
+        assert!(!is_valid(phone).await, "An ended session still works");
+        assert!(is_valid(laptop).await, "The wrong session was ended");
+
+        let logout_resp = client
+            .post("http://127.0.0.1:8080/logout")
+            .bearer_auth(laptop)
+            .send()
+            .await
+            .expect("Failed to log out");
+        assert!(logout_resp.status().is_success(), "Logout failed");
+
+        assert!(!is_valid(laptop).await, "A logged out session still works");
+    }
 }